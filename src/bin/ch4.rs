@@ -1,4 +1,4 @@
-use raytracer::point_vector::{PointVector,vector,point};
+use raytracer::point_vector::{vector,point};
 use raytracer::canvas::Canvas;
 use raytracer::color::Color;
 use raytracer::transformations;
@@ -14,8 +14,8 @@ fn main() {
     let mut p = v + center;
     let rotation_matrix = transformations::rotation_z(2.0*PI/12.0);
 
-    for ring in 0..1 {
-        for r in 0..12 {
+    for _ring in 0..1 {
+        for _r in 0..12 {
             for i in 0..3 {
                 for j in 0..3 {
                     cv.write_pixel_f32(p.x+i as f32, p.y+j as f32, c);
@@ -0,0 +1,55 @@
+use raytracer::point_vector::{vector,point};
+use raytracer::shapes::Sphere;
+use raytracer::transformations;
+use raytracer::color::Color;
+use raytracer::point_light::PointLight;
+use raytracer::world::World;
+use raytracer::camera::Camera;
+use raytracer::canvas::PpmFormat;
+use std::fs::File;
+use std::io::{self,BufWriter};
+use std::f32::consts::PI;
+
+fn main() -> io::Result<()> {
+    let mut floor = Sphere::new();
+    floor.set_transform(&transformations::scaling(10, 0.01, 10));
+    floor.material.color = Color::new(1, 0.9, 0.9);
+    floor.material.specular = 0.0;
+
+    let mut left_wall = Sphere::new();
+    left_wall.set_transform(&(&(&(&transformations::translate(0, 0, 5) * &transformations::rotation_y(-PI/4.0)) * &transformations::rotation_x(PI/2.0)) * &transformations::scaling(10, 0.01, 10)));
+    left_wall.material = floor.material.clone();
+
+    let mut right_wall = Sphere::new();
+    right_wall.set_transform(&(&(&(&transformations::translate(0, 0, 5) * &transformations::rotation_y(PI/4.0)) * &transformations::rotation_x(PI/2.0)) * &transformations::scaling(10, 0.01, 10)));
+    right_wall.material = floor.material.clone();
+
+    let mut middle = Sphere::new();
+    middle.set_transform(&transformations::translate(-0.5, 1, 0.5));
+    middle.material.color = Color::new(0.1, 1, 0.5);
+    middle.material.diffuse = 0.7;
+    middle.material.specular = 0.3;
+
+    let mut right = Sphere::new();
+    right.set_transform(&(&transformations::translate(1.5, 0.5, -0.5) * &transformations::scaling(0.5, 0.5, 0.5)));
+    right.material.color = Color::new(0.5, 1, 0.1);
+    right.material.diffuse = 0.7;
+    right.material.specular = 0.3;
+
+    let mut left = Sphere::new();
+    left.set_transform(&(&transformations::translate(-1.5, 0.33, -0.75) * &transformations::scaling(0.33, 0.33, 0.33)));
+    left.material.color = Color::new(1, 0.8, 0.1);
+    left.material.diffuse = 0.7;
+    left.material.specular = 0.3;
+
+    let mut world = World::new();
+    world.objects = vec![Box::new(floor), Box::new(left_wall), Box::new(right_wall), Box::new(middle), Box::new(right), Box::new(left)];
+    world.lights.push(PointLight::new(point(-10, 10, -10), Color::new(1, 1, 1)));
+
+    let mut camera = Camera::new(200, 100, PI/3.0);
+    camera.set_transform(&transformations::view_transform(point(0, 1.5, -5), point(0, 1, 0), vector(0, 1, 0)));
+
+    let cv = camera.render(&world);
+    let file = BufWriter::new(File::create("ch7.ppm")?);
+    cv.write_ppm(file, PpmFormat::Ascii)
+}
@@ -0,0 +1,280 @@
+use crate::point_vector::{PointVector,point};
+use crate::matrix::Matrix;
+use crate::ray::Ray;
+use crate::shapes::check_axis;
+
+
+// Axis-aligned box; an empty box has min above max so that adding anything replaces it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub min: PointVector,
+    pub max: PointVector
+}
+
+impl Default for BoundingBox {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+impl BoundingBox {
+
+    pub fn new(min: PointVector, max: PointVector) -> BoundingBox {
+        BoundingBox {
+            min,
+            max
+        }
+    }
+
+    pub fn empty() -> BoundingBox {
+        BoundingBox::new(
+            point(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+            point(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY)
+        )
+    }
+
+    pub fn infinite() -> BoundingBox {
+        BoundingBox::new(
+            point(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+            point(f32::INFINITY, f32::INFINITY, f32::INFINITY)
+        )
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn is_finite(&self) -> bool {
+        [self.min, self.max].iter().all(|p| p.x.is_finite() && p.y.is_finite() && p.z.is_finite())
+    }
+
+    pub fn add_point(&mut self, p: PointVector) {
+        self.min = point(self.min.x.min(p.x), self.min.y.min(p.y), self.min.z.min(p.z));
+        self.max = point(self.max.x.max(p.x), self.max.y.max(p.y), self.max.z.max(p.z));
+    }
+
+    pub fn merge(&mut self, other: &BoundingBox) {
+        if other.is_empty() {
+            return
+        }
+        self.add_point(other.min);
+        self.add_point(other.max);
+    }
+
+    pub fn contains_point(&self, p: PointVector) -> bool {
+        self.min.x <= p.x && p.x <= self.max.x &&
+        self.min.y <= p.y && p.y <= self.max.y &&
+        self.min.z <= p.z && p.z <= self.max.z
+    }
+
+    pub fn contains_box(&self, other: &BoundingBox) -> bool {
+        self.contains_point(other.min) && self.contains_point(other.max)
+    }
+
+    pub fn centroid(&self) -> PointVector {
+        point(
+            (self.min.x + self.max.x) / 2.0,
+            (self.min.y + self.max.y) / 2.0,
+            (self.min.z + self.max.z) / 2.0
+        )
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        if self.is_empty() {
+            return 0.0
+        }
+        let dx = self.max.x - self.min.x;
+        let dy = self.max.y - self.min.y;
+        let dz = self.max.z - self.min.z;
+        2.0 * (dx * dy + dy * dz + dz * dx)
+    }
+
+    // Box around all eight transformed corners. Matrix products turn infinite
+    // extents into NaN, so unbounded boxes stay unbounded in every direction.
+    pub fn transform(&self, m: &Matrix) -> BoundingBox {
+        if self.is_empty() {
+            return *self
+        }
+        if !self.is_finite() {
+            return BoundingBox::infinite()
+        }
+        let corners = [
+            self.min,
+            point(self.min.x, self.min.y, self.max.z),
+            point(self.min.x, self.max.y, self.min.z),
+            point(self.min.x, self.max.y, self.max.z),
+            point(self.max.x, self.min.y, self.min.z),
+            point(self.max.x, self.min.y, self.max.z),
+            point(self.max.x, self.max.y, self.min.z),
+            self.max
+        ];
+        let mut transformed = BoundingBox::empty();
+        for corner in corners.iter() {
+            transformed.add_point(m * corner);
+        }
+        transformed
+    }
+
+    pub fn intersects(&self, r: &Ray) -> bool {
+        if self.is_empty() {
+            return false
+        }
+        let (xtmin, xtmax) = check_axis(r.origin.x, r.direction.x, self.min.x, self.max.x);
+        let (ytmin, ytmax) = check_axis(r.origin.y, r.direction.y, self.min.y, self.max.y);
+        let (ztmin, ztmax) = check_axis(r.origin.z, r.direction.z, self.min.z, self.max.z);
+        let tmin = xtmin.max(ytmin).max(ztmin);
+        let tmax = xtmax.min(ytmax).min(ztmax);
+        tmin <= tmax
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests_bounds {
+    use super::*;
+    use crate::point_vector::vector;
+    use crate::transformations::{rotation_x,rotation_y};
+    use crate::utils::assert_float_eq;
+    use std::f32::consts::{FRAC_PI_4,SQRT_2};
+
+    #[test]
+    fn test_empty() {
+        let b = BoundingBox::empty();
+        assert!(b.is_empty());
+        assert_eq!(b.min.x, f32::INFINITY);
+        assert_eq!(b.max.x, f32::NEG_INFINITY);
+        assert_float_eq(b.surface_area(), 0.0);
+    }
+
+    #[test]
+    fn test_add_point() {
+        let mut b = BoundingBox::empty();
+        b.add_point(point(-5, 2, 0));
+        b.add_point(point(7, 0, -3));
+        assert_eq!(b.min, point(-5, 0, -3));
+        assert_eq!(b.max, point(7, 2, 0));
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut b1 = BoundingBox::new(point(-5, -2, 0), point(7, 4, 4));
+        let b2 = BoundingBox::new(point(8, -7, -2), point(14, 2, 8));
+        b1.merge(&b2);
+        assert_eq!(b1.min, point(-5, -7, -2));
+        assert_eq!(b1.max, point(14, 4, 8));
+        b1.merge(&BoundingBox::empty());
+        assert_eq!(b1.min, point(-5, -7, -2));
+    }
+
+    #[test]
+    fn test_contains_point() {
+        let b = BoundingBox::new(point(5, -2, 0), point(11, 4, 7));
+        let cases = [
+            (point(5, -2, 0), true),
+            (point(11, 4, 7), true),
+            (point(8, 1, 3), true),
+            (point(3, 0, 3), false),
+            (point(8, -4, 3), false),
+            (point(8, 1, -1), false),
+            (point(13, 1, 3), false),
+            (point(8, 5, 3), false),
+            (point(8, 1, 8), false)
+        ];
+        for (p, expected) in cases.iter() {
+            assert_eq!(b.contains_point(*p), *expected);
+        }
+    }
+
+    #[test]
+    fn test_contains_box() {
+        let b = BoundingBox::new(point(5, -2, 0), point(11, 4, 7));
+        let cases = [
+            (point(5, -2, 0), point(11, 4, 7), true),
+            (point(6, -1, 1), point(10, 3, 6), true),
+            (point(4, -3, -1), point(10, 3, 6), false),
+            (point(6, -1, 1), point(12, 5, 8), false)
+        ];
+        for (min, max, expected) in cases.iter() {
+            assert_eq!(b.contains_box(&BoundingBox::new(*min, *max)), *expected);
+        }
+    }
+
+    #[test]
+    fn test_transform() {
+        let b = BoundingBox::new(point(-1, -1, -1), point(1, 1, 1));
+        let m = &rotation_x(FRAC_PI_4) * &rotation_y(FRAC_PI_4);
+        let b2 = b.transform(&m);
+        assert_eq!(b2.min, point(-SQRT_2, -1.70711, -1.70711));
+        assert_eq!(b2.max, point(SQRT_2, 1.70711, 1.70711));
+    }
+
+    #[test]
+    fn test_transform_unbounded() {
+        let b = BoundingBox::new(point(f32::NEG_INFINITY, 0, f32::NEG_INFINITY), point(f32::INFINITY, 0, f32::INFINITY));
+        let b2 = b.transform(&rotation_x(FRAC_PI_4));
+        assert_eq!(b2.min.y, f32::NEG_INFINITY);
+        assert_eq!(b2.max.y, f32::INFINITY);
+        assert!(BoundingBox::empty().transform(&rotation_x(FRAC_PI_4)).is_empty());
+    }
+
+    #[test]
+    fn test_surface_area() {
+        let b = BoundingBox::new(point(0, 0, 0), point(1, 2, 3));
+        assert_float_eq(b.surface_area(), 22.0);
+    }
+
+    #[test]
+    fn test_intersects_cube() {
+        let b = BoundingBox::new(point(-1, -1, -1), point(1, 1, 1));
+        let cases = [
+            (point(5, 0.5, 0), vector(-1, 0, 0), true),
+            (point(-5, 0.5, 0), vector(1, 0, 0), true),
+            (point(0.5, 5, 0), vector(0, -1, 0), true),
+            (point(0.5, -5, 0), vector(0, 1, 0), true),
+            (point(0.5, 0, 5), vector(0, 0, -1), true),
+            (point(0.5, 0, -5), vector(0, 0, 1), true),
+            (point(0, 0.5, 0), vector(0, 0, 1), true),
+            (point(-2, 0, 0), vector(2, 4, 6), false),
+            (point(0, -2, 0), vector(6, 2, 4), false),
+            (point(0, 0, -2), vector(4, 6, 2), false),
+            (point(2, 0, 2), vector(0, 0, -1), false),
+            (point(0, 2, 2), vector(0, -1, 0), false),
+            (point(2, 2, 0), vector(-1, 0, 0), false)
+        ];
+        for (origin, direction, expected) in cases.iter() {
+            let r = Ray::new(*origin, direction.normalize());
+            assert_eq!(b.intersects(&r), *expected);
+        }
+    }
+
+    #[test]
+    fn test_intersects_non_cubic() {
+        let b = BoundingBox::new(point(5, -2, 0), point(11, 4, 7));
+        let cases = [
+            (point(15, 1, 2), vector(-1, 0, 0), true),
+            (point(-5, -1, 4), vector(1, 0, 0), true),
+            (point(7, 6, 5), vector(0, -1, 0), true),
+            (point(9, -5, 6), vector(0, 1, 0), true),
+            (point(8, 2, 12), vector(0, 0, -1), true),
+            (point(6, 0, -5), vector(0, 0, 1), true),
+            (point(8, 1, 3.5), vector(0, 0, 1), true),
+            (point(9, -1, -8), vector(2, 4, 6), false),
+            (point(8, 3, -4), vector(6, 2, 4), false),
+            (point(9, -1, -2), vector(4, 6, 2), false),
+            (point(4, 0, 9), vector(0, 0, -1), false),
+            (point(8, 6, -1), vector(0, -1, 0), false),
+            (point(12, 5, 4), vector(-1, 0, 0), false)
+        ];
+        for (origin, direction, expected) in cases.iter() {
+            let r = Ray::new(*origin, direction.normalize());
+            assert_eq!(b.intersects(&r), *expected);
+        }
+    }
+
+    #[test]
+    fn test_empty_never_intersects() {
+        let r = Ray::new(point(0, 0, -5), vector(0, 0, 1));
+        assert!(!BoundingBox::empty().intersects(&r));
+    }
+}
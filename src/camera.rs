@@ -0,0 +1,473 @@
+use crate::matrix::{Matrix,Matrix4};
+use crate::error::Result;
+use crate::ray::Ray;
+use crate::point_vector::point;
+use crate::canvas::Canvas;
+use crate::world::World;
+use crate::color::Color;
+use crate::random::Rng;
+use std::sync::Mutex;
+use std::thread;
+
+
+// How many rays are averaged into each pixel
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Sampling {
+    // One ray through the pixel's center
+    #[default]
+    Single,
+    // n×n rays through the centers of a regular grid of sub-pixel cells
+    Stratified(usize),
+    // n×n rays, each at a random spot inside its sub-pixel cell; the same seed
+    // always gives the same image
+    Jittered { samples: usize, seed: u64 },
+    // One ray per pixel corner; a pixel is split into quarters, up to max_depth
+    // times, wherever its corners differ by more than threshold in any channel
+    Adaptive { threshold: f32, max_depth: usize }
+}
+
+
+fn exceeds_contrast(corners: &[Color; 4], threshold: f32) -> bool {
+    let spread = |channel: fn(&Color) -> f32| {
+        let values = corners.iter().map(channel);
+        let max = values.clone().fold(f32::NEG_INFINITY, f32::max);
+        let min = values.fold(f32::INFINITY, f32::min);
+        max - min
+    };
+    spread(|c| c.red) > threshold || spread(|c| c.green) > threshold || spread(|c| c.blue) > threshold
+}
+
+
+pub struct Camera {
+    hsize: usize,
+    vsize: usize,
+    field_of_view: f32,
+    transform: Matrix,
+    inverse_transform: Matrix4,
+    half_width: f32,
+    half_height: f32,
+    pixel_size: f32,
+    sampling: Sampling
+}
+
+impl Camera {
+    pub fn new(hsize: usize, vsize: usize, field_of_view: impl Into<f64>) -> Camera {
+        let field_of_view = field_of_view.into() as f32;
+        let half_view = (field_of_view / 2.0).tan();
+        let aspect = hsize as f32 / vsize as f32;
+        let (half_width, half_height) = if aspect >= 1.0 {
+            (half_view, half_view / aspect)
+        } else {
+            (half_view * aspect, half_view)
+        };
+        Camera {
+            hsize,
+            vsize,
+            field_of_view,
+            transform: Matrix::identity(4),
+            inverse_transform: Matrix4::identity(),
+            half_width,
+            half_height,
+            pixel_size: half_width * 2.0 / hsize as f32,
+            sampling: Sampling::default()
+        }
+    }
+
+    pub fn hsize(&self) -> usize {
+        self.hsize
+    }
+
+    pub fn vsize(&self) -> usize {
+        self.vsize
+    }
+
+    pub fn field_of_view(&self) -> f32 {
+        self.field_of_view
+    }
+
+    pub fn pixel_size(&self) -> f32 {
+        self.pixel_size
+    }
+
+    pub fn transform(&self) -> &Matrix {
+        &self.transform
+    }
+
+    pub fn set_transform(&mut self, m: &Matrix) {
+        self.try_set_transform(m).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    // Keeps the previous transform when m isn't an invertible 4x4 matrix
+    pub fn try_set_transform(&mut self, m: &Matrix) -> Result<()> {
+        self.inverse_transform = Matrix4::try_from_matrix(m)?.try_inverse()?;
+        self.transform = m.clone();
+        Ok(())
+    }
+
+    pub fn sampling(&self) -> Sampling {
+        self.sampling
+    }
+
+    pub fn set_sampling(&mut self, sampling: Sampling) {
+        self.sampling = sampling;
+    }
+
+    pub fn ray_for_pixel(&self, px: usize, py: usize) -> Ray {
+        self.ray_for_pixel_offset(px, py, 0.5, 0.5)
+    }
+
+    // dx and dy place the ray inside the pixel, from 0 at its top left corner to 1
+    pub fn ray_for_pixel_offset(&self, px: usize, py: usize, dx: f32, dy: f32) -> Ray {
+        // Offsets from the edge of the canvas to the sample point
+        let xoffset = (px as f32 + dx) * self.pixel_size;
+        let yoffset = (py as f32 + dy) * self.pixel_size;
+        // The camera looks toward -z, so +x is to the left
+        let world_x = self.half_width - xoffset;
+        let world_y = self.half_height - yoffset;
+
+        let pixel = &self.inverse_transform * &point(world_x, world_y, -1);
+        let origin = &self.inverse_transform * &point(0, 0, 0);
+        let direction = (pixel - origin).normalize();
+        Ray::new(origin, direction)
+    }
+
+    // Renders on every available core
+    pub fn render(&self, world: &World) -> Canvas {
+        let threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        self.render_with_threads(world, threads)
+    }
+
+    // Threads pull rows one at a time, so busy rows don't hold up the others.
+    // Every pixel is computed the same way whichever thread takes it, so the
+    // image doesn't depend on the thread count.
+    pub fn render_with_threads(&self, world: &World, threads: usize) -> Canvas {
+        let mut image = Canvas::new(self.hsize, self.vsize);
+        let rows = Mutex::new(image.rows_mut());
+        thread::scope(|scope| {
+            for _ in 0..threads.max(1) {
+                scope.spawn(|| loop {
+                    let next = rows.lock().unwrap().next();
+                    let Some((y, row)) = next else { break };
+                    self.render_row(world, y, row);
+                });
+            }
+        });
+        drop(rows);
+        image
+    }
+
+    fn render_row(&self, world: &World, y: usize, row: &mut [Color]) {
+        if let Sampling::Adaptive { threshold, max_depth } = self.sampling {
+            // Neighbouring pixels in the row share their corner samples
+            let top: Vec<Color> = (0..=self.hsize).map(|x| self.corner_sample(world, x, y)).collect();
+            let bottom: Vec<Color> = (0..=self.hsize).map(|x| self.corner_sample(world, x, y + 1)).collect();
+            for (x, pixel) in row.iter_mut().enumerate() {
+                let corners = [top[x], top[x + 1], bottom[x], bottom[x + 1]];
+                *pixel = self.refine(world, (x, y), (0.0, 0.0, 1.0), corners, threshold, max_depth);
+            }
+            return
+        }
+        for (x, pixel) in row.iter_mut().enumerate() {
+            *pixel = self.color_for_pixel(world, x, y);
+        }
+    }
+
+    pub fn color_for_pixel(&self, world: &World, px: usize, py: usize) -> Color {
+        match self.sampling {
+            Sampling::Single => world.color_at(&self.ray_for_pixel(px, py)),
+            Sampling::Stratified(n) => self.supersample(world, px, py, n, || 0.5),
+            Sampling::Jittered { samples, seed } => {
+                // Seeded per pixel so the result doesn't depend on render order
+                let pixel_index = (py * self.hsize + px) as u64;
+                let mut rng = Rng::new(seed ^ pixel_index.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+                self.supersample(world, px, py, samples, || rng.next_f32())
+            }
+            Sampling::Adaptive { threshold, max_depth } => {
+                let corners = [
+                    self.corner_sample(world, px, py),
+                    self.corner_sample(world, px + 1, py),
+                    self.corner_sample(world, px, py + 1),
+                    self.corner_sample(world, px + 1, py + 1)
+                ];
+                self.refine(world, (px, py), (0.0, 0.0, 1.0), corners, threshold, max_depth)
+            }
+        }
+    }
+
+    // Color at the top left corner of pixel (px, py)
+    fn corner_sample(&self, world: &World, px: usize, py: usize) -> Color {
+        world.color_at(&self.ray_for_pixel_offset(px, py, 0.0, 0.0))
+    }
+
+    // Color of the square of the pixel at (dx, dy) with side size, given the colors at
+    // its top left, top right, bottom left and bottom right corners
+    fn refine(&self, world: &World, (px, py): (usize, usize), (dx, dy, size): (f32, f32, f32), corners: [Color; 4], threshold: f32, depth: usize) -> Color {
+        if depth == 0 || !exceeds_contrast(&corners, threshold) {
+            return (corners[0] + corners[1] + corners[2] + corners[3]) / 4
+        }
+        let half = size / 2.0;
+        let sample = |sx: f32, sy: f32| world.color_at(&self.ray_for_pixel_offset(px, py, sx, sy));
+        let [top_left, top_right, bottom_left, bottom_right] = corners;
+        let top = sample(dx + half, dy);
+        let left = sample(dx, dy + half);
+        let center = sample(dx + half, dy + half);
+        let right = sample(dx + size, dy + half);
+        let bottom = sample(dx + half, dy + size);
+        let quarters = [
+            self.refine(world, (px, py), (dx, dy, half), [top_left, top, left, center], threshold, depth - 1),
+            self.refine(world, (px, py), (dx + half, dy, half), [top, top_right, center, right], threshold, depth - 1),
+            self.refine(world, (px, py), (dx, dy + half, half), [left, center, bottom_left, bottom], threshold, depth - 1),
+            self.refine(world, (px, py), (dx + half, dy + half, half), [center, right, bottom, bottom_right], threshold, depth - 1)
+        ];
+        (quarters[0] + quarters[1] + quarters[2] + quarters[3]) / 4
+    }
+
+    // Averages n×n rays, one per grid cell, placed within each cell by jitter
+    fn supersample(&self, world: &World, px: usize, py: usize, n: usize, mut jitter: impl FnMut() -> f32) -> Color {
+        let n = n.max(1);
+        let mut sum = Color::new(0, 0, 0);
+        for i in 0..n {
+            for j in 0..n {
+                let dx = (j as f32 + jitter()) / n as f32;
+                let dy = (i as f32 + jitter()) / n as f32;
+                sum = sum + world.color_at(&self.ray_for_pixel_offset(px, py, dx, dy));
+            }
+        }
+        sum / (n * n) as f32
+    }
+}
+
+
+#[cfg(test)]
+mod tests_camera {
+    use super::*;
+    use crate::point_vector::{point,vector};
+    use crate::transformations;
+    use crate::color::Color;
+    use crate::utils::assert_float_eq;
+    use crate::world::default_world;
+    use crate::error::Error;
+    use std::f32::consts::PI;
+
+    #[test]
+    fn test_new() {
+        let c = Camera::new(160, 120, PI / 2.0);
+        assert_eq!(c.hsize(), 160);
+        assert_eq!(c.vsize(), 120);
+        assert_float_eq(c.field_of_view(), PI / 2.0);
+        assert_eq!(*c.transform(), Matrix::identity(4));
+    }
+
+    #[test]
+    fn test_pixel_size_horizontal() {
+        let c = Camera::new(200, 125, PI / 2.0);
+        assert_float_eq(c.pixel_size(), 0.01);
+    }
+
+    #[test]
+    fn test_pixel_size_vertical() {
+        let c = Camera::new(125, 200, PI / 2.0);
+        assert_float_eq(c.pixel_size(), 0.01);
+    }
+
+    #[test]
+    fn test_ray_for_pixel_center() {
+        let c = Camera::new(201, 101, PI / 2.0);
+        let r = c.ray_for_pixel(100, 50);
+        assert_eq!(r.origin, point(0, 0, 0));
+        assert_eq!(r.direction, vector(0, 0, -1));
+    }
+
+    #[test]
+    fn test_ray_for_pixel_corner() {
+        let c = Camera::new(201, 101, PI / 2.0);
+        let r = c.ray_for_pixel(0, 0);
+        assert_eq!(r.origin, point(0, 0, 0));
+        assert_eq!(r.direction, vector(0.66519, 0.33259, -0.66851));
+    }
+
+    #[test]
+    fn test_ray_for_pixel_transformed() {
+        let mut c = Camera::new(201, 101, PI / 2.0);
+        c.set_transform(&(&transformations::rotation_y(PI / 4.0) * &transformations::translate(0, -2, 5)));
+        let r = c.ray_for_pixel(100, 50);
+        let h = 2.0_f32.sqrt() / 2.0;
+        assert_eq!(r.origin, point(0, 2, -5));
+        assert_eq!(r.direction, vector(h, 0, -h));
+    }
+
+    #[test]
+    fn test_try_set_transform_keeps_previous_on_error() {
+        let mut c = Camera::new(201, 101, PI / 2.0);
+        let translation = transformations::translate(0, -2, 5);
+        c.set_transform(&translation);
+        assert_eq!(c.try_set_transform(&transformations::scaling(1, 0, 1)), Err(Error::SingularMatrix));
+        assert_eq!(*c.transform(), translation);
+        assert_eq!(c.ray_for_pixel(100, 50).origin, point(0, 2, -5));
+    }
+
+    #[test]
+    fn test_render() {
+        let w = default_world();
+        let mut c = Camera::new(11, 11, PI / 2.0);
+        c.set_transform(&transformations::view_transform(point(0, 0, -5), point(0, 0, 0), vector(0, 1, 0)));
+        let image = c.render(&w);
+        assert_eq!(image.get_pixel(5, 5), Color::new(0.38066, 0.47583, 0.2855));
+    }
+
+    #[test]
+    fn test_render_threads_bit_identical() {
+        let w = default_world();
+        let mut c = Camera::new(40, 30, PI / 3.0);
+        c.set_transform(&transformations::view_transform(point(0, 1.5, -5), point(0, 0, 0), vector(0, 1, 0)));
+        let single = c.render_with_threads(&w, 1);
+        for threads in [2, 4, 7] {
+            let multi = c.render_with_threads(&w, threads);
+            for y in 0..30 {
+                for x in 0..40 {
+                    let (a, b) = (single.get_pixel(x, y), multi.get_pixel(x, y));
+                    assert_eq!(a.red.to_bits(), b.red.to_bits());
+                    assert_eq!(a.green.to_bits(), b.green.to_bits());
+                    assert_eq!(a.blue.to_bits(), b.blue.to_bits());
+                }
+            }
+        }
+    }
+
+    fn edge_camera(sampling: Sampling) -> Camera {
+        let mut c = Camera::new(21, 21, PI / 3.0);
+        c.set_transform(&transformations::view_transform(point(0, 0, -5), point(0, 0, 0), vector(0, 1, 0)));
+        c.set_sampling(sampling);
+        c
+    }
+
+    fn same_pixels(a: &Canvas, b: &Canvas) -> bool {
+        (0..a.height).all(|y| (0..a.width).all(|x| {
+            let (p, q) = (a.get_pixel(x, y), b.get_pixel(x, y));
+            p.red.to_bits() == q.red.to_bits() && p.green.to_bits() == q.green.to_bits() && p.blue.to_bits() == q.blue.to_bits()
+        }))
+    }
+
+    #[test]
+    fn test_default_sampling() {
+        let c = Camera::new(160, 120, PI / 2.0);
+        assert_eq!(c.sampling(), Sampling::Single);
+    }
+
+    #[test]
+    fn test_ray_for_pixel_offset() {
+        let c = Camera::new(201, 101, PI / 2.0);
+        let centered = c.ray_for_pixel_offset(0, 0, 0.5, 0.5);
+        assert_eq!(centered.direction, c.ray_for_pixel(0, 0).direction);
+        let corner = c.ray_for_pixel_offset(100, 50, 0.0, 0.0);
+        assert_eq!(corner.direction, vector(0.005, 0.005, -1).normalize());
+    }
+
+    #[test]
+    fn test_single_stratified_sample_matches_single() {
+        let w = default_world();
+        let single = edge_camera(Sampling::Single).render_with_threads(&w, 2);
+        let stratified = edge_camera(Sampling::Stratified(1)).render_with_threads(&w, 2);
+        assert!(same_pixels(&single, &stratified));
+    }
+
+    #[test]
+    fn test_stratified_averages_cell_centers() {
+        let w = default_world();
+        let c = edge_camera(Sampling::Stratified(2));
+        let (px, py) = (14, 10);
+        let mut expected = Color::new(0, 0, 0);
+        for (dx, dy) in [(0.25, 0.25), (0.75, 0.25), (0.25, 0.75), (0.75, 0.75)] {
+            expected = expected + w.color_at(&c.ray_for_pixel_offset(px, py, dx, dy));
+        }
+        assert_eq!(c.color_for_pixel(&w, px, py), expected / 4);
+    }
+
+    #[test]
+    fn test_stratified_softens_edges() {
+        let w = default_world();
+        let single = edge_camera(Sampling::Single);
+        let stratified = edge_camera(Sampling::Stratified(4));
+        // Find a pixel on the silhouette of the outer sphere along the middle row
+        let x = (0..20).find(|&x| single.color_for_pixel(&w, x, 10) != single.color_for_pixel(&w, x + 1, 10)).unwrap();
+        let background = single.color_for_pixel(&w, 0, 10);
+        let smoothed = stratified.color_for_pixel(&w, x, 10);
+        assert_ne!(smoothed, single.color_for_pixel(&w, x, 10));
+        assert_ne!(smoothed, background);
+    }
+
+    #[test]
+    fn test_jittered_reproducible() {
+        let w = default_world();
+        let c = edge_camera(Sampling::Jittered { samples: 2, seed: 7 });
+        let a = c.render_with_threads(&w, 1);
+        let b = c.render_with_threads(&w, 3);
+        assert!(same_pixels(&a, &b));
+        let other = edge_camera(Sampling::Jittered { samples: 2, seed: 8 }).render_with_threads(&w, 1);
+        assert!(!same_pixels(&a, &other));
+    }
+
+    fn corner_average(w: &World, c: &Camera, px: usize, py: usize) -> Color {
+        let mut sum = Color::new(0, 0, 0);
+        for (dx, dy) in [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (1.0, 1.0)] {
+            sum = sum + w.color_at(&c.ray_for_pixel_offset(px, py, dx, dy));
+        }
+        sum / 4
+    }
+
+    #[test]
+    fn test_adaptive_flat_pixels_use_corners_only() {
+        let w = default_world();
+        let c = edge_camera(Sampling::Adaptive { threshold: 0.1, max_depth: 3 });
+        assert_eq!(c.color_for_pixel(&w, 0, 0), corner_average(&w, &c, 0, 0));
+        assert_eq!(c.color_for_pixel(&w, 0, 0), Color::new(0, 0, 0));
+    }
+
+    #[test]
+    fn test_adaptive_subdivides_high_contrast_pixels() {
+        let w = default_world();
+        let c = edge_camera(Sampling::Adaptive { threshold: 0.1, max_depth: 3 });
+        let unrefined = edge_camera(Sampling::Adaptive { threshold: 0.1, max_depth: 0 });
+        let x = (0..20).find(|&x| {
+            let corners = [
+                w.color_at(&c.ray_for_pixel_offset(x, 10, 0.0, 0.0)),
+                w.color_at(&c.ray_for_pixel_offset(x, 10, 1.0, 0.0)),
+                w.color_at(&c.ray_for_pixel_offset(x, 10, 0.0, 1.0)),
+                w.color_at(&c.ray_for_pixel_offset(x, 10, 1.0, 1.0))
+            ];
+            exceeds_contrast(&corners, 0.1)
+        }).unwrap();
+        assert_eq!(unrefined.color_for_pixel(&w, x, 10), corner_average(&w, &c, x, 10));
+        assert_ne!(c.color_for_pixel(&w, x, 10), corner_average(&w, &c, x, 10));
+    }
+
+    #[test]
+    fn test_adaptive_render_matches_per_pixel() {
+        let w = default_world();
+        let c = edge_camera(Sampling::Adaptive { threshold: 0.05, max_depth: 2 });
+        let image = c.render_with_threads(&w, 3);
+        for y in 0..21 {
+            for x in 0..21 {
+                let expected = c.color_for_pixel(&w, x, y);
+                assert_eq!(image.get_pixel(x, y).red.to_bits(), expected.red.to_bits());
+                assert_eq!(image.get_pixel(x, y).green.to_bits(), expected.green.to_bits());
+                assert_eq!(image.get_pixel(x, y).blue.to_bits(), expected.blue.to_bits());
+            }
+        }
+    }
+
+    #[test]
+    fn test_exceeds_contrast() {
+        let black = Color::new(0, 0, 0);
+        let grey = Color::new(0, 0.05, 0);
+        assert!(!exceeds_contrast(&[black, black, black, grey], 0.1));
+        assert!(exceeds_contrast(&[black, black, Color::new(0, 0, 0.2), grey], 0.1));
+    }
+
+    #[test]
+    fn test_world_is_shareable_across_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<World>();
+        assert_send_sync::<Camera>();
+    }
+}
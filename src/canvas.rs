@@ -1,4 +1,7 @@
 use crate::color::Color;
+use crate::png;
+use std::io::{self,Write};
+use std::fmt;
 
 fn clamp(ipt: i32, min: i32, max: i32) -> i32 {
     if ipt > max {
@@ -9,6 +12,56 @@ fn clamp(ipt: i32, min: i32, max: i32) -> i32 {
         ipt
     }
 }
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PpmFormat {
+    // P3: plain-text values
+    Ascii,
+    // P6: one byte per channel
+    Binary
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PngFormat {
+    Rgb,
+    // Adds a fully opaque alpha channel
+    Rgba
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PpmError {
+    // The file doesn't start with P3 or P6
+    UnsupportedFormat(String),
+    // The input stopped while the named part was still being read
+    UnexpectedEnd(&'static str),
+    InvalidNumber { field: &'static str, text: String },
+    // PPM max values go from 1 to 65535
+    InvalidMaxValue(u32),
+    ValueOutOfRange { value: u32, max_value: u32 },
+    // A side is zero pixels long
+    EmptyImage { width: usize, height: usize },
+    // A side is longer than MAX_PPM_SIZE, so the canvas isn't allocated
+    TooLarge { width: usize, height: usize }
+}
+
+// Longest side from_ppm accepts, well above any image rendered here
+pub const MAX_PPM_SIZE: usize = 16384;
+
+impl fmt::Display for PpmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PpmError::UnsupportedFormat(magic) => write!(f, "unsupported PPM format {:?}, expected P3 or P6", magic),
+            PpmError::UnexpectedEnd(what) => write!(f, "PPM data ended while reading the {}", what),
+            PpmError::InvalidNumber { field, text } => write!(f, "invalid PPM {} {:?}", field, text),
+            PpmError::InvalidMaxValue(max_value) => write!(f, "PPM max value {} is outside 1 to 65535", max_value),
+            PpmError::ValueOutOfRange { value, max_value } => write!(f, "PPM value {} exceeds the max value {}", value, max_value),
+            PpmError::EmptyImage { width, height } => write!(f, "PPM image of {}x{} pixels is empty", width, height),
+            PpmError::TooLarge { width, height } => write!(f, "PPM image of {}x{} pixels is too large", width, height)
+        }
+    }
+}
+
+impl std::error::Error for PpmError {}
+
 pub struct Canvas {
     pub width: usize,
     pub height: usize,
@@ -25,10 +78,10 @@ impl Canvas {
         }
     }
 
-    fn set_all(&mut self, c: Color) {
+    pub fn set_all(&mut self, c: Color) {
         for w in 0..self.width {
             for h in 0..self.height {
-                self.write_pixel(w, h, c.clone())
+                self.write_pixel(w, h, c)
             }
         }
     }
@@ -45,77 +98,207 @@ impl Canvas {
         self.write_pixel(w, h, c);
     }
 
-    fn get_pixel(&self, w: usize, h: usize) -> Color {
+    pub fn get_pixel(&self, w: usize, h: usize) -> Color {
         self.pixels[h][w]
     }
 
+    // Each row can be handed to a different thread and filled independently
+    pub fn rows_mut(&mut self) -> impl Iterator<Item = (usize, &mut [Color])> {
+        self.pixels.iter_mut().map(|row| row.as_mut_slice()).enumerate()
+    }
+
     pub fn to_ppm(&self) -> String {
-        let mut s = String::new();
-        s.push_str("P3");
-        s.push('\n');
-        s.push_str(&format!("{} {}", self.width, self.height));
-        s.push('\n');
-        s.push_str("255");
-        s.push('\n');
-        let mut row_size = 0;
-        for i in 0..self.height {
-            for j in 0..self.width {
-                let c = self.pixels[i][j];
-                let red = clamp((c.red * 255.0).round() as i32, 0, 255).to_string();
-                if (red.len() + row_size) > 70 {
-                    s.push_str("\n");
-                    s.push_str(&red);
-                    s.push_str(" ");
-                    row_size = red.len();
-                } else if (red.len() + row_size) == 70 {
-                    s.push_str(&red);
-                    s.push_str("\n");
-                    row_size = 0;
-                } else  {
-                    s.push_str(&red);
-                    s.push_str(" ");
-                    row_size = row_size + red.len();
+        let mut buf = Vec::new();
+        self.write_ppm(&mut buf, PpmFormat::Ascii).expect("writing to a Vec cannot fail");
+        String::from_utf8(buf).expect("ASCII PPM is valid UTF-8")
+    }
+
+    // Streams the image one row at a time, so the whole file never sits in memory
+    pub fn write_ppm<W: Write>(&self, mut w: W, format: PpmFormat) -> io::Result<()> {
+        match format {
+            PpmFormat::Ascii => {
+                write!(w, "P3\n{} {}\n255\n", self.width, self.height)?;
+                // Line length carries over from one row to the next
+                let mut row_size = 0;
+                let mut line = String::new();
+                for row in self.pixels.iter() {
+                    line.clear();
+                    for c in row.iter() {
+                        push_ppm_value(&mut line, to_byte(c.red), &mut row_size);
+                        push_ppm_value(&mut line, to_byte(c.green), &mut row_size);
+                        push_ppm_value(&mut line, to_byte(c.blue), &mut row_size);
+                    }
+                    // Replace the last separator with the end of the row
+                    if line.pop().is_some() {
+                        line.push('\n');
+                    }
+                    w.write_all(line.as_bytes())?;
                 }
-                let green = clamp((c.green * 255.0).round() as i32, 0, 255).to_string();
-                if (green.len() + row_size) > 70 {
-                    s.push_str("\n");
-                    s.push_str(&green);
-                    s.push_str(" ");
-                    row_size = green.len();
-                } else if (green.len() + row_size) == 70 {
-                    s.push_str(&green);
-                    s.push_str("\n");
-                    row_size = 0;
-                } else  {
-                    s.push_str(&green);
-                    s.push_str(" ");
-                    row_size = row_size + green.len();
+            }
+            PpmFormat::Binary => {
+                write!(w, "P6\n{} {}\n255\n", self.width, self.height)?;
+                let mut bytes = Vec::with_capacity(self.width * 3);
+                for row in self.pixels.iter() {
+                    bytes.clear();
+                    for c in row.iter() {
+                        bytes.extend_from_slice(&[to_byte(c.red), to_byte(c.green), to_byte(c.blue)]);
+                    }
+                    w.write_all(&bytes)?;
                 }
-                let blue = clamp((c.blue * 255.0).round() as i32, 0, 255).to_string();
-                if (blue.len() + row_size) > 70 {
-                    s.push_str("\n");
-                    s.push_str(&blue);
-                    s.push_str(" ");
-                    row_size = blue.len();
-                } else if (blue.len() + row_size) == 70 {
-                    s.push_str(&blue);
-                    s.push_str("\n");
-                    row_size = 0;
-                } else  {
-                    s.push_str(&blue);
-                    s.push_str(" ");
-                    row_size = row_size + blue.len();
+            }
+        }
+        w.flush()
+    }
+
+    // Reads plain (P3) or binary (P6) PPM data, scaling values by the file's max value
+    pub fn from_ppm(data: &[u8]) -> Result<Canvas, PpmError> {
+        let mut reader = PpmReader { data, pos: 0 };
+        let magic = reader.next_token("format")?;
+        let binary = match magic {
+            b"P3" => false,
+            b"P6" => true,
+            _ => return Err(PpmError::UnsupportedFormat(String::from_utf8_lossy(magic).into_owned()))
+        };
+        let width = reader.next_number("width")? as usize;
+        let height = reader.next_number("height")? as usize;
+        if width == 0 || height == 0 {
+            return Err(PpmError::EmptyImage { width, height })
+        }
+        if width > MAX_PPM_SIZE || height > MAX_PPM_SIZE {
+            return Err(PpmError::TooLarge { width, height })
+        }
+        let max_value = reader.next_number("max value")?;
+        if max_value == 0 || max_value > 65535 {
+            return Err(PpmError::InvalidMaxValue(max_value))
+        }
+        let sample_count = width * height * 3;
+        let sample_size = if max_value < 256 { 1 } else { 2 };
+        if binary {
+            // Exactly one whitespace byte separates the header from the raster
+            reader.pos += 1;
+            if reader.remaining() < sample_count * sample_size {
+                return Err(PpmError::UnexpectedEnd("pixel data"))
+            }
+        } else if reader.remaining() < sample_count {
+            // Every plain value takes at least one byte
+            return Err(PpmError::UnexpectedEnd("pixel data"))
+        }
+
+        let mut canvas = Canvas::new(width, height);
+        for row in canvas.pixels.iter_mut() {
+            for pixel in row.iter_mut() {
+                let mut channels = [0.0; 3];
+                for channel in channels.iter_mut() {
+                    let value = if binary {
+                        reader.next_binary_sample(sample_size)
+                    } else {
+                        reader.next_number("pixel value")?
+                    };
+                    if value > max_value {
+                        return Err(PpmError::ValueOutOfRange { value, max_value })
+                    }
+                    *channel = value as f32 / max_value as f32;
                 }
+                *pixel = Color::new(channels[0], channels[1], channels[2]);
             }
-            // Remove the last space
-            s.pop();
-            s.push('\n');
         }
-        s
-        
+        Ok(canvas)
+    }
+
+    pub fn write_png<W: Write>(&self, w: W, format: PngFormat) -> io::Result<()> {
+        let channels = match format {
+            PngFormat::Rgb => 3,
+            PngFormat::Rgba => 4
+        };
+        let rows = self.pixels.iter().map(|row| {
+            let mut bytes = Vec::with_capacity(self.width * channels);
+            for c in row.iter() {
+                bytes.extend_from_slice(&[to_byte(c.red), to_byte(c.green), to_byte(c.blue)]);
+                if format == PngFormat::Rgba {
+                    bytes.push(255);
+                }
+            }
+            bytes
+        });
+        png::write_png(w, self.width, self.height, channels, rows)
+    }
+}
+
+fn to_byte(channel: f32) -> u8 {
+    clamp((channel * 255.0).round() as i32, 0, 255) as u8
+}
+
+// Appends one value to an ASCII PPM row, wrapping before lines get past 70 characters
+fn push_ppm_value(line: &mut String, value: u8, row_size: &mut usize) {
+    let value = value.to_string();
+    if (value.len() + *row_size) > 70 {
+        line.push('\n');
+        line.push_str(&value);
+        line.push(' ');
+        *row_size = value.len();
+    } else if (value.len() + *row_size) == 70 {
+        line.push_str(&value);
+        line.push('\n');
+        *row_size = 0;
+    } else {
+        line.push_str(&value);
+        line.push(' ');
+        *row_size += value.len();
     }
 }
 
+struct PpmReader<'a> {
+    data: &'a [u8],
+    pos: usize
+}
+
+impl<'a> PpmReader<'a> {
+    fn remaining(&self) -> usize {
+        self.data.len().saturating_sub(self.pos)
+    }
+
+    // Comments run from # to the end of the line
+    fn skip_whitespace_and_comments(&mut self) {
+        while let Some(&byte) = self.data.get(self.pos) {
+            if byte == b'#' {
+                while self.pos < self.data.len() && self.data[self.pos] != b'\n' && self.data[self.pos] != b'\r' {
+                    self.pos += 1;
+                }
+            } else if byte.is_ascii_whitespace() {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn next_token(&mut self, field: &'static str) -> Result<&'a [u8], PpmError> {
+        self.skip_whitespace_and_comments();
+        let start = self.pos;
+        while self.pos < self.data.len() && !self.data[self.pos].is_ascii_whitespace() && self.data[self.pos] != b'#' {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Err(PpmError::UnexpectedEnd(field))
+        }
+        Ok(&self.data[start..self.pos])
+    }
+
+    fn next_number(&mut self, field: &'static str) -> Result<u32, PpmError> {
+        let token = self.next_token(field)?;
+        let text = String::from_utf8_lossy(token);
+        text.parse().map_err(|_| PpmError::InvalidNumber { field, text: text.into_owned() })
+    }
+
+    // Binary samples are one byte, or two big-endian bytes when the max value is over 255
+    fn next_binary_sample(&mut self, size: usize) -> u32 {
+        let value = self.data[self.pos..self.pos + size].iter().fold(0, |acc, &b| (acc << 8) | b as u32);
+        self.pos += size;
+        value
+    }
+}
+
+
 #[cfg(test)]
 mod tests_color {
     use super::*;
@@ -141,6 +324,19 @@ mod tests_color {
         assert_ne!(cv.get_pixel(2,3), green);
     }
 
+    #[test]
+    fn test_rows_mut() {
+        let mut cv = Canvas::new(3,2);
+        let red = Color::new(1,0,0);
+        for (h, row) in cv.rows_mut() {
+            assert_eq!(row.len(), 3);
+            row[h] = red;
+        }
+        assert_eq!(cv.get_pixel(0,0), red);
+        assert_eq!(cv.get_pixel(1,1), red);
+        assert_eq!(cv.get_pixel(1,0), Color::new(0,0,0));
+    }
+
     #[test]
     fn test_toppm() {
         let cv = Canvas::new(10,20);
@@ -182,6 +378,181 @@ mod tests_color {
         let mut s = cv.to_ppm();
         assert_eq!(s.pop().unwrap(),'\n')
     }
+
+    #[test]
+    fn test_write_ppm_ascii_matches_to_ppm() {
+        let mut cv = Canvas::new(10,2);
+        cv.set_all(Color::new(1,0.8,0.6));
+        cv.write_pixel(3, 1, Color::new(0.1, 0, 2));
+        let mut buf = Vec::new();
+        cv.write_ppm(&mut buf, PpmFormat::Ascii).unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), cv.to_ppm());
+    }
+
+    #[test]
+    fn test_write_ppm_binary() {
+        let mut cv = Canvas::new(2,2);
+        cv.write_pixel(0, 0, Color::new(1.5, 0, 0));
+        cv.write_pixel(1, 1, Color::new(0, 0.5, -1));
+        let mut buf = Vec::new();
+        cv.write_ppm(&mut buf, PpmFormat::Binary).unwrap();
+        let header = b"P6\n2 2\n255\n";
+        assert_eq!(&buf[..header.len()], header);
+        assert_eq!(&buf[header.len()..], &[255, 0, 0, 0, 0, 0, 0, 0, 0, 0, 128, 0]);
+    }
+
+    #[test]
+    fn test_write_png() {
+        let mut cv = Canvas::new(2,1);
+        cv.write_pixel(0, 0, Color::new(1.5, 0.5, 0));
+        let mut rgb = Vec::new();
+        cv.write_png(&mut rgb, PngFormat::Rgb).unwrap();
+        assert_eq!(&rgb[..8], &[137, 80, 78, 71, 13, 10, 26, 10]);
+        // IHDR is first: 8-bit depth, truecolor
+        assert_eq!(&rgb[12..16], b"IHDR");
+        assert_eq!(&rgb[24..26], &[8, 2]);
+        // After the zlib and stored block headers come the filter byte and the pixels
+        assert_eq!(&rgb[48..55], &[0, 255, 128, 0, 0, 0, 0]);
+        let mut rgba = Vec::new();
+        cv.write_png(&mut rgba, PngFormat::Rgba).unwrap();
+        assert_eq!(rgba[25], 6);
+        assert_eq!(&rgba[48..57], &[0, 255, 128, 0, 255, 0, 0, 0, 255]);
+        assert_eq!(&rgba[rgba.len() - 8..rgba.len() - 4], b"IEND");
+    }
+
+    #[test]
+    fn test_from_ppm_wrong_magic() {
+        let err = Canvas::from_ppm(b"P32\n1 1\n255\n0 0 0\n").err().unwrap();
+        assert_eq!(err, PpmError::UnsupportedFormat("P32".to_string()));
+    }
+
+    #[test]
+    fn test_from_ppm_size() {
+        let cv = Canvas::from_ppm(b"P3\n10 2\n255\n0 0 0  0 0 0  0 0 0  0 0 0  0 0 0\n0 0 0  0 0 0  0 0 0  0 0 0  0 0 0\n0 0 0  0 0 0  0 0 0  0 0 0  0 0 0\n0 0 0  0 0 0  0 0 0  0 0 0  0 0 0\n").unwrap();
+        assert_eq!(cv.width, 10);
+        assert_eq!(cv.height, 2);
+    }
+
+    #[test]
+    fn test_from_ppm_pixels() {
+        let cv = Canvas::from_ppm(b"P3\n4 3\n255\n255 127 0  0 127 255  127 255 0  255 255 255\n0 0 0  255 0 0  0 255 0  0 0 255\n255 255 0  0 255 255  255 0 255  127 127 127\n").unwrap();
+        let cases = [
+            (0, 0, Color::new(1, 0.49804, 0)),
+            (1, 0, Color::new(0, 0.49804, 1)),
+            (2, 0, Color::new(0.49804, 1, 0)),
+            (3, 0, Color::new(1, 1, 1)),
+            (0, 1, Color::new(0, 0, 0)),
+            (1, 1, Color::new(1, 0, 0)),
+            (2, 1, Color::new(0, 1, 0)),
+            (3, 1, Color::new(0, 0, 1)),
+            (0, 2, Color::new(1, 1, 0)),
+            (1, 2, Color::new(0, 1, 1)),
+            (2, 2, Color::new(1, 0, 1)),
+            (3, 2, Color::new(0.49804, 0.49804, 0.49804))
+        ];
+        for (x, y, c) in cases.iter() {
+            assert_eq!(cv.get_pixel(*x, *y), *c);
+        }
+    }
+
+    #[test]
+    fn test_from_ppm_comments() {
+        let cv = Canvas::from_ppm(b"P3\n# this is a comment\n2 1\n# this, too\n255\n# another comment\n255 255 255\n# oh, no, comments in the pixel data!\n255 0 255\n").unwrap();
+        assert_eq!(cv.get_pixel(1, 0), Color::new(1, 0, 1));
+    }
+
+    #[test]
+    fn test_from_ppm_triple_spans_lines() {
+        let cv = Canvas::from_ppm(b"P3\n1 1\n255\n51\n153\n\n204\n").unwrap();
+        assert_eq!(cv.get_pixel(0, 0), Color::new(0.2, 0.6, 0.8));
+    }
+
+    #[test]
+    fn test_from_ppm_max_value() {
+        let cv = Canvas::from_ppm(b"P3\n2 2\n100\n100 100 100  50 50 50\n75 50 25  0 0 0\n").unwrap();
+        assert_eq!(cv.get_pixel(0, 1), Color::new(0.75, 0.5, 0.25));
+    }
+
+    #[test]
+    fn test_from_ppm_binary() {
+        let mut data = b"P6 # binary\n2 1 255\n".to_vec();
+        data.extend_from_slice(&[255, 0, 51, 0, 255, 0]);
+        let cv = Canvas::from_ppm(&data).unwrap();
+        assert_eq!(cv.get_pixel(0, 0), Color::new(1, 0, 0.2));
+        assert_eq!(cv.get_pixel(1, 0), Color::new(0, 1, 0));
+    }
+
+    #[test]
+    fn test_from_ppm_binary_sixteen_bit() {
+        let mut data = b"P6\n1 1\n65535\n".to_vec();
+        data.extend_from_slice(&[0xFF, 0xFF, 0x80, 0x00, 0x00, 0x00]);
+        let cv = Canvas::from_ppm(&data).unwrap();
+        assert_eq!(cv.get_pixel(0, 0), Color::new(1, 0.5, 0));
+    }
+
+    #[test]
+    fn test_from_ppm_round_trip() {
+        let mut cv = Canvas::new(5,3);
+        cv.write_pixel(0, 0, Color::new(1.5, 0, 0));
+        cv.write_pixel(2, 1, Color::new(0, 0.5, 0));
+        cv.write_pixel(4, 2, Color::new(-0.5, 0, 1));
+        for format in [PpmFormat::Ascii, PpmFormat::Binary] {
+            let mut buf = Vec::new();
+            cv.write_ppm(&mut buf, format).unwrap();
+            let read = Canvas::from_ppm(&buf).unwrap();
+            assert_eq!(read.get_pixel(0, 0), Color::new(1, 0, 0));
+            assert_eq!(read.get_pixel(2, 1), Color::new(0, 128.0 / 255.0, 0));
+            assert_eq!(read.get_pixel(4, 2), Color::new(0, 0, 1));
+        }
+    }
+
+    #[test]
+    fn test_from_ppm_checked_in_image() {
+        let original = include_str!("../test.ppm");
+        let cv = Canvas::from_ppm(original.as_bytes()).unwrap();
+        assert_eq!((cv.width, cv.height), (900, 550));
+        assert_eq!(cv.to_ppm(), original);
+    }
+
+    #[test]
+    fn test_from_ppm_errors() {
+        let cases: [(&[u8], PpmError); 9] = [
+            (b"", PpmError::UnexpectedEnd("format")),
+            (b"P3\n2\n", PpmError::UnexpectedEnd("height")),
+            (b"P3\n2 x\n255\n", PpmError::InvalidNumber { field: "height", text: "x".to_string() }),
+            (b"P3\n1 1\n0\n0 0 0\n", PpmError::InvalidMaxValue(0)),
+            (b"P3\n1 1\n100\n0 101 0\n", PpmError::ValueOutOfRange { value: 101, max_value: 100 }),
+            (b"P6\n2 2\n255\n\x00\x00\x00", PpmError::UnexpectedEnd("pixel data")),
+            (b"P3 4000000000 0 255\n", PpmError::EmptyImage { width: 4000000000, height: 0 }),
+            (b"P3\n0 2\n255\n", PpmError::EmptyImage { width: 0, height: 2 }),
+            (b"P6 4000000000 4000000000 255\n", PpmError::TooLarge { width: 4000000000, height: 4000000000 })
+        ];
+        for (data, expected) in cases.iter() {
+            assert_eq!(Canvas::from_ppm(data).err().unwrap(), *expected);
+        }
+        assert_eq!(PpmError::InvalidMaxValue(0).to_string(), "PPM max value 0 is outside 1 to 65535");
+    }
+
+    struct FailingWriter;
+
+    impl Write for FailingWriter {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("disk full"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_ppm_reports_errors() {
+        let cv = Canvas::new(2,2);
+        for format in [PpmFormat::Ascii, PpmFormat::Binary] {
+            let err = cv.write_ppm(FailingWriter, format).unwrap_err();
+            assert_eq!(err.to_string(), "disk full");
+        }
+    }
 }
 
 
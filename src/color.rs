@@ -1,5 +1,5 @@
 use std::ops::{Add,Sub,Neg,Mul,Div};
-use crate::utils::{compare_float,assert_float_eq};
+use crate::utils::compare_float;
 
 #[derive(PartialOrd, Clone, Copy, Debug)]
 pub struct Color {
@@ -0,0 +1,79 @@
+use crate::point_vector::PointVector;
+use crate::canvas::PpmError;
+use std::fmt;
+
+
+// Everything in the crate that can fail on bad input reports one of these
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    // A matrix row doesn't have as many values as the first row
+    RaggedMatrix { row: usize, expected: usize, found: usize },
+    // Left columns must match right rows
+    ShapeMismatch { left: (usize, usize), right: (usize, usize) },
+    NotSquare { rows: usize, cols: usize },
+    // Transforms must be 4x4 to become a Matrix4
+    NotFourByFour { rows: usize, cols: usize },
+    // The determinant is zero, as with a scale of 0 along some axis
+    SingularMatrix,
+    // Only 4x4 matrices with a bottom row of 0 0 0 1 split into translation, rotation and scale
+    NotAffine,
+    InvalidRayOrigin(PointVector),
+    InvalidRayDirection(PointVector),
+    Ppm(PpmError)
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::RaggedMatrix { row, expected, found } => write!(f, "matrix row {} has {} values, expected {}", row, found, expected),
+            Error::ShapeMismatch { left, right } => write!(f, "cannot multiply a {}x{} matrix by a {}x{} matrix", left.0, left.1, right.0, right.1),
+            Error::NotSquare { rows, cols } => write!(f, "a {}x{} matrix is not square", rows, cols),
+            Error::NotFourByFour { rows, cols } => write!(f, "expected a 4x4 matrix, got {}x{}", rows, cols),
+            Error::SingularMatrix => write!(f, "matrix is not invertible"),
+            Error::NotAffine => write!(f, "matrix is not an affine 4x4 transform"),
+            Error::InvalidRayOrigin(p) => write!(f, "ray origin must be a point, got w = {}", p.w),
+            Error::InvalidRayDirection(v) => write!(f, "ray direction must be a vector, got w = {}", v.w),
+            Error::Ppm(e) => write!(f, "{}", e)
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Ppm(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl From<PpmError> for Error {
+    fn from(e: PpmError) -> Error {
+        Error::Ppm(e)
+    }
+}
+
+
+#[cfg(test)]
+mod tests_error {
+    use super::*;
+    use crate::point_vector::vector;
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Error::RaggedMatrix { row: 2, expected: 4, found: 3 }.to_string(), "matrix row 2 has 3 values, expected 4");
+        assert_eq!(Error::ShapeMismatch { left: (4, 4), right: (3, 3) }.to_string(), "cannot multiply a 4x4 matrix by a 3x3 matrix");
+        assert_eq!(Error::SingularMatrix.to_string(), "matrix is not invertible");
+        assert_eq!(Error::NotAffine.to_string(), "matrix is not an affine 4x4 transform");
+        assert_eq!(Error::InvalidRayOrigin(vector(1, 2, 3)).to_string(), "ray origin must be a point, got w = 0");
+    }
+
+    #[test]
+    fn test_from_ppm_error() {
+        let e: Error = PpmError::UnexpectedEnd("width").into();
+        assert_eq!(e, Error::Ppm(PpmError::UnexpectedEnd("width")));
+        assert!(std::error::Error::source(&e).is_some());
+    }
+}
@@ -1,7 +1,7 @@
-use crate::shapes::{Sphere,Shape};
+use crate::shapes::Shape;
 use crate::ray::Ray;
-use crate::utils::compare_float;
-use crate::point_vector::{PointVector,point,vector,reflect};
+use crate::utils::{compare_float,EPSILON};
+use crate::point_vector::{PointVector,reflect};
 use crate::point_light::PointLight;
 use crate::color::Color;
 use crate::material::Material;
@@ -20,17 +20,17 @@ impl<'a> Intersections<'a> {
         self.v.sort_by(|a, b| a.t.partial_cmp(&b.t).unwrap())
     }
 
-    pub fn get_intersections_vector(&self) -> & Vec<Intersection>{
+    pub fn get_intersections_vector(&self) -> & Vec<Intersection<'_>>{
         &self.v
     }
 
-    pub fn new_from_intersect(s: &'a Sphere, r: &Ray) -> Intersections<'a> {
+    pub fn new_from_intersect(s: &'a dyn Shape, r: &Ray) -> Intersections<'a> {
         let i = intersect(s, r);
         Self::new(i)
     }
 
-    pub fn hit(self) -> Option<Intersection<'a>> {
-        if self.v.len() == 0 {
+    pub fn hit(&self) -> Option<Intersection<'a>> {
+        if self.v.is_empty() {
             return None
         }
         let mut curr_hit = self.v[0];
@@ -51,51 +51,128 @@ impl<'a> Intersections<'a> {
 #[derive(Debug,Clone,Copy)]
 pub struct Intersection<'a> {
     pub t: f32,
-    pub obj: &'a Sphere
+    pub obj: &'a dyn Shape,
+    // Where on the surface the hit landed, for shapes that care (e.g. barycentric for triangles)
+    pub u: f32,
+    pub v: f32
 }
 
 impl<'a> Intersection<'a> {
-    pub fn new(t: impl Into<f64>, obj: &Sphere) -> Intersection {
-        Intersection {t: t.into() as f32, obj}
+    pub fn new(t: impl Into<f64>, obj: &'a dyn Shape) -> Intersection<'a> {
+        Intersection {t: t.into() as f32, obj, u: 0.0, v: 0.0}
+    }
+
+    pub fn new_with_uv(t: impl Into<f64>, obj: &'a dyn Shape, u: impl Into<f64>, v: impl Into<f64>) -> Intersection<'a> {
+        Intersection {t: t.into() as f32, obj, u: u.into() as f32, v: v.into() as f32}
     }
 }
 
 impl PartialEq for Intersection<'_> {
     fn eq(&self, other: &Self) -> bool {
-        compare_float(&self.t, &other.t) && (self.obj == other.obj)
+        compare_float(&self.t, &other.t) && std::ptr::addr_eq(self.obj, other.obj)
         
     }
 }
 impl Eq for Intersection<'_> {}
 
-pub fn intersect<'a>(s: &'a Sphere, r: &Ray) -> Vec<Intersection<'a>>{
-    let r = r.transform(&s.transform.inverse());
-    let sphere_to_ray = r.origin - s.center;
-    let a = r.direction.dot(&r.direction);
-    let b = 2.0 * r.direction.dot(&sphere_to_ray);
-    let c = sphere_to_ray.dot(&sphere_to_ray) - 1.0;
-    let discriminant = b.powi(2) - 4.0 * a * c;
-    if discriminant < 0.0 {
-        vec![]
+pub fn intersect<'a>(s: &'a dyn Shape, r: &Ray) -> Vec<Intersection<'a>>{
+    s.intersect(r)
+}
+
+
+pub struct Computations<'a> {
+    pub t: f32,
+    pub obj: &'a dyn Shape,
+    pub point: PointVector,
+    // Point nudged slightly above the surface to avoid self-shadowing
+    pub over_point: PointVector,
+    pub eyev: PointVector,
+    pub normalv: PointVector,
+    // Point nudged slightly below the surface where refracted rays start
+    pub under_point: PointVector,
+    pub reflectv: PointVector,
+    pub inside: bool,
+    // Refractive indices of the materials being exited and entered
+    pub n1: f32,
+    pub n2: f32
+}
+
+pub fn prepare_computations<'a>(i: &Intersection<'a>, r: &Ray, xs: &Intersections<'a>) -> Computations<'a> {
+    let point = r.position(i.t);
+    let eyev = r.direction.negate();
+    let mut normalv = i.obj.normal_at_hit(point, i);
+    let inside = normalv.dot(&eyev) < 0.0;
+    if inside {
+        normalv = normalv.negate();
     }
-    else {
-        let t1 = (-b - discriminant.sqrt()) / (2.0*a) ;
-        let t2 = (-b + discriminant.sqrt()) / (2.0*a) ;
-        let mut v = vec![t1,t2];
-        v.sort_by(|a,b| a.partial_cmp(b).unwrap());
-        vec![Intersection::new(v[0],&s), Intersection::new(v[1],&s)]
 
-        
+    // Walk the sorted intersections tracking which objects the ray is inside. The hit
+    // is matched exactly, as a neighbour within EPSILON would give the wrong n1/n2.
+    let is_hit = |x: &Intersection| x.t == i.t && std::ptr::addr_eq(x.obj, i.obj);
+    let mut n1 = 1.0;
+    let mut n2 = 1.0;
+    let mut containers: Vec<&dyn Shape> = vec![];
+    for x in xs.v.iter() {
+        if is_hit(x) {
+            if let Some(last) = containers.last() {
+                n1 = last.material().refractive_index;
+            }
+        }
+        match containers.iter().position(|c| std::ptr::addr_eq(*c, x.obj)) {
+            Some(index) => { containers.remove(index); },
+            None => containers.push(x.obj)
+        }
+        if is_hit(x) {
+            if let Some(last) = containers.last() {
+                n2 = last.material().refractive_index;
+            }
+            break;
+        }
+    }
+
+    Computations {
+        t: i.t,
+        obj: i.obj,
+        point,
+        over_point: point + normalv * EPSILON,
+        under_point: point - normalv * EPSILON,
+        eyev,
+        normalv,
+        reflectv: reflect(r.direction, normalv),
+        inside,
+        n1,
+        n2
     }
+}
 
+// Schlick's approximation of the Fresnel reflectance
+pub fn schlick(comps: &Computations) -> f32 {
+    let mut cos = comps.eyev.dot(&comps.normalv);
+    if comps.n1 > comps.n2 {
+        let n = comps.n1 / comps.n2;
+        let sin2_t = n.powi(2) * (1.0 - cos.powi(2));
+        if sin2_t > 1.0 {
+            return 1.0
+        }
+        cos = (1.0 - sin2_t).sqrt();
+    }
+    let r0 = ((comps.n1 - comps.n2) / (comps.n1 + comps.n2)).powi(2);
+    r0 + (1.0 - r0) * (1.0 - cos).powi(5)
 }
 
 
-pub fn lighting(m: Material, light: PointLight, position: PointVector, eyev: PointVector, normalv: PointVector) -> Color {
-    let effective_color = m.color * light.intensity;
-    let mut specular : Color;
+pub fn lighting(m: &Material, obj: &dyn Shape, light: PointLight, position: PointVector, eyev: PointVector, normalv: PointVector, in_shadow: bool) -> Color {
+    let color = match &m.pattern {
+        Some(pattern) => pattern.pattern_at_shape(obj, position),
+        None => m.color
+    };
+    let effective_color = color * light.intensity;
+    let specular : Color;
     let diffuse : Color;
     let ambient = effective_color * m.ambient;
+    if in_shadow {
+        return ambient
+    }
     
     let light_vector = (light.position - position).normalize();
     let cos_light_normal = light_vector.dot(&normalv);//(light_vector.magnitude()*normalv.magnitude());
@@ -128,6 +205,11 @@ pub fn lighting(m: Material, light: PointLight, position: PointVector, eyev: Poi
 #[cfg(test)]
 mod tests_shapes {
     use super::*;
+    use crate::shapes::{Sphere,Plane};
+    use crate::utils::assert_float_eq;
+    use crate::patterns::StripePattern;
+    use std::sync::Arc;
+    use crate::point_vector::{point,vector};
     use crate::transformations;
 
     #[test]
@@ -185,15 +267,15 @@ mod tests_shapes {
         }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_intersect_6() {
         let r = Ray::new(point(0,0,5), vector(0, 0, 1));
         let s = Sphere::new();
         let xs = intersect(&s, &r);
 
         assert_eq!(xs.len(), 2);
-        assert_eq!(*xs[0].obj, s);
-        assert_eq!(*xs[1].obj, s);
-        assert_eq!(std::ptr::eq(xs[0].obj,&s),true);
+        assert!(std::ptr::addr_eq(xs[0].obj, &s));
+        assert!(std::ptr::addr_eq(xs[1].obj, &s));
         }
     
     #[test]
@@ -206,11 +288,12 @@ mod tests_shapes {
         s2.center = p;
         let i = Intersection::new(t, &s);
         assert_eq!(i.t,t);
-        assert_eq!(*i.obj, s);
-        assert_ne!(*i.obj, s2);
+        assert!(std::ptr::addr_eq(i.obj, &s));
+        assert!(!std::ptr::addr_eq(i.obj, &s2));
     }
 
     #[test]
+    #[allow(clippy::useless_vec)]
     fn test_intersections(){
         let s = Sphere::new();
         let i1 = Intersection::new(1.0, &s);
@@ -222,6 +305,7 @@ mod tests_shapes {
     }
 
     #[test]
+    #[allow(clippy::useless_vec)]
     fn test_intersections2(){
         let s = Sphere::new();
         let i1 = Intersection::new(1.0, &s);
@@ -233,6 +317,16 @@ mod tests_shapes {
     }
 
     #[test]
+    fn test_intersection_uv() {
+        let s = Sphere::new();
+        let i = Intersection::new_with_uv(3.5, &s, 0.2, 0.4);
+        assert_eq!(i.t, 3.5);
+        assert_eq!(i.u, 0.2);
+        assert_eq!(i.v, 0.4);
+    }
+
+    #[test]
+    #[allow(unused_mut)]
     fn test_hit1() {
         let s = Sphere::new();
         let i1 = Intersection::new(1, &s);
@@ -243,6 +337,7 @@ mod tests_shapes {
     }
 
     #[test]
+    #[allow(unused_mut)]
     fn test_hit2() {
         let s = Sphere::new();
         let i1 = Intersection::new(-1, &s);
@@ -253,6 +348,7 @@ mod tests_shapes {
     }
 
     #[test]
+    #[allow(unused_mut)]
     fn test_hit3() {
         let s = Sphere::new();
         let i1 = Intersection::new(-2, &s);
@@ -263,6 +359,7 @@ mod tests_shapes {
     }
 
     #[test]
+    #[allow(unused_mut)]
     fn test_hit4() {
         let s = Sphere::new();
         let i1 = Intersection::new(5, &s);
@@ -275,6 +372,7 @@ mod tests_shapes {
     }
 
     #[test]
+    #[allow(unused_mut)]
     fn test_hit5() {
         let mut xs = Intersections::new(vec![]);
         let i = xs.hit();
@@ -302,6 +400,174 @@ mod tests_shapes {
         assert_eq!(xs.len(), 0);
     }
 
+    #[test]
+    fn test_prepare_computations() {
+        let r = Ray::new(point(0, 0, -5), vector(0, 0, 1));
+        let s = Sphere::new();
+        let i = Intersection::new(4, &s);
+        let comps = prepare_computations(&i, &r, &Intersections::new(vec![i]));
+        assert_eq!(comps.t, i.t);
+        assert!(std::ptr::addr_eq(comps.obj, &s));
+        assert_eq!(comps.point, point(0, 0, -1));
+        assert_eq!(comps.eyev, vector(0, 0, -1));
+        assert_eq!(comps.normalv, vector(0, 0, -1));
+        assert!(!comps.inside);
+    }
+
+    #[test]
+    fn test_prepare_computations_inside() {
+        let r = Ray::new(point(0, 0, 0), vector(0, 0, 1));
+        let s = Sphere::new();
+        let i = Intersection::new(1, &s);
+        let comps = prepare_computations(&i, &r, &Intersections::new(vec![i]));
+        assert_eq!(comps.point, point(0, 0, 1));
+        assert_eq!(comps.eyev, vector(0, 0, -1));
+        assert!(comps.inside);
+        assert_eq!(comps.normalv, vector(0, 0, -1));
+    }
+
+    #[test]
+    fn test_prepare_computations_over_point() {
+        let r = Ray::new(point(0, 0, -5), vector(0, 0, 1));
+        let mut s = Sphere::new();
+        s.set_transform(&transformations::translate(0, 0, 1));
+        let i = Intersection::new(5, &s);
+        let comps = prepare_computations(&i, &r, &Intersections::new(vec![i]));
+        assert!(comps.over_point.z < -EPSILON / 2.0);
+        assert!(comps.point.z > comps.over_point.z);
+    }
+
+    #[test]
+    fn test_prepare_computations_reflectv() {
+        let p = Plane::new();
+        let h = 2.0_f32.sqrt() / 2.0;
+        let r = Ray::new(point(0, 1, -1), vector(0, -h, h));
+        let i = Intersection::new(2.0_f32.sqrt(), &p);
+        let comps = prepare_computations(&i, &r, &Intersections::new(vec![i]));
+        assert_eq!(comps.reflectv, vector(0, h, h));
+    }
+
+    fn glass_sphere() -> Sphere {
+        let mut s = Sphere::new();
+        s.material.transparency = 1.0;
+        s.material.refractive_index = 1.5;
+        s
+    }
+
+    #[test]
+    fn test_prepare_computations_n1_n2() {
+        let mut a = glass_sphere();
+        a.set_transform(&transformations::scaling(2, 2, 2));
+        let mut b = glass_sphere();
+        b.set_transform(&transformations::translate(0, 0, -0.25));
+        b.material.refractive_index = 2.0;
+        let mut c = glass_sphere();
+        c.set_transform(&transformations::translate(0, 0, 0.25));
+        c.material.refractive_index = 2.5;
+        let r = Ray::new(point(0, 0, -4), vector(0, 0, 1));
+        let xs = Intersections::new(vec![
+            Intersection::new(2, &a), Intersection::new(2.75, &b), Intersection::new(3.25, &c),
+            Intersection::new(4.75, &b), Intersection::new(5.25, &c), Intersection::new(6, &a)
+        ]);
+        let expected = [(1.0, 1.5), (1.5, 2.0), (2.0, 2.5), (2.5, 2.5), (2.5, 1.5), (1.5, 1.0)];
+        for (index, (n1, n2)) in expected.iter().enumerate() {
+            let comps = prepare_computations(&xs.v[index], &r, &xs);
+            assert_eq!(comps.n1, *n1);
+            assert_eq!(comps.n2, *n2);
+        }
+    }
+
+    #[test]
+    fn test_prepare_computations_n1_n2_nearly_coincident() {
+        let a = glass_sphere();
+        let mut b = glass_sphere();
+        b.material.refractive_index = 2.0;
+        let r = Ray::new(point(0, 0, -4), vector(0, 0, 1));
+
+        let xs = Intersections::new(vec![
+            Intersection::new(2, &a), Intersection::new(3, &b), Intersection::new(4, &a), Intersection::new(4.00001, &b)
+        ]);
+        let comps = prepare_computations(&xs.v[2], &r, &xs);
+        assert_eq!((comps.n1, comps.n2), (2.0, 2.0));
+        let comps = prepare_computations(&xs.v[3], &r, &xs);
+        assert_eq!((comps.n1, comps.n2), (2.0, 1.0));
+
+        // A grazing entry and exit of the same object
+        let xs = Intersections::new(vec![Intersection::new(2, &a), Intersection::new(2.00001, &a)]);
+        let comps = prepare_computations(&xs.v[1], &r, &xs);
+        assert_eq!((comps.n1, comps.n2), (1.5, 1.0));
+    }
+
+    #[test]
+    fn test_prepare_computations_under_point() {
+        let r = Ray::new(point(0, 0, -5), vector(0, 0, 1));
+        let mut s = glass_sphere();
+        s.set_transform(&transformations::translate(0, 0, 1));
+        let i = Intersection::new(5, &s);
+        let xs = Intersections::new(vec![i]);
+        let comps = prepare_computations(&i, &r, &xs);
+        assert!(comps.under_point.z > EPSILON / 2.0);
+        assert!(comps.point.z < comps.under_point.z);
+    }
+
+    #[test]
+    fn test_schlick_total_internal_reflection() {
+        let s = glass_sphere();
+        let h = 2.0_f32.sqrt() / 2.0;
+        let r = Ray::new(point(0, 0, h), vector(0, 1, 0));
+        let xs = Intersections::new(vec![Intersection::new(-h, &s), Intersection::new(h, &s)]);
+        let comps = prepare_computations(&xs.v[1], &r, &xs);
+        assert_eq!(schlick(&comps), 1.0);
+    }
+
+    #[test]
+    fn test_schlick_perpendicular() {
+        let s = glass_sphere();
+        let r = Ray::new(point(0, 0, 0), vector(0, 1, 0));
+        let xs = Intersections::new(vec![Intersection::new(-1, &s), Intersection::new(1, &s)]);
+        let comps = prepare_computations(&xs.v[1], &r, &xs);
+        assert_float_eq(schlick(&comps), 0.04);
+    }
+
+    #[test]
+    fn test_schlick_small_angle() {
+        let s = glass_sphere();
+        let r = Ray::new(point(0, 0.99, -2), vector(0, 0, 1));
+        let xs = Intersections::new(vec![Intersection::new(1.8589, &s)]);
+        let comps = prepare_computations(&xs.v[0], &r, &xs);
+        assert_float_eq(schlick(&comps), 0.48873);
+    }
+
+    #[test]
+    fn test_lighting_shadow() {
+        let m = Material::default();
+        let position = point(0, 0, 0);
+        let eyev = vector(0, 0, -1);
+        let normalv = vector(0, 0, -1);
+        let light = PointLight::new(point(0, 0, -10),Color::new(1,1,1));
+        let result = lighting(&m, &Sphere::new(), light, position, eyev, normalv, true);
+        assert_eq!(result, Color::new(0.1,0.1,0.1))
+    }
+
+    #[test]
+    fn test_lighting_pattern() {
+        let m = Material {
+            pattern: Some(Arc::new(StripePattern::new(Color::new(1, 1, 1), Color::new(0, 0, 0)))),
+            ambient: 1.0,
+            diffuse: 0.0,
+            specular: 0.0,
+            ..Material::default()
+        };
+        let s = Sphere::new();
+        let eyev = vector(0, 0, -1);
+        let normalv = vector(0, 0, -1);
+        let light = PointLight::new(point(0, 0, -10),Color::new(1,1,1));
+        let c1 = lighting(&m, &s, light, point(0.9, 0, 0), eyev, normalv, false);
+        let c2 = lighting(&m, &s, light, point(1.1, 0, 0), eyev, normalv, false);
+        assert_eq!(c1, Color::new(1, 1, 1));
+        assert_eq!(c2, Color::new(0, 0, 0));
+    }
+
     #[test]
     fn test_lighting_1() {
         let m = Material::default();
@@ -309,7 +575,7 @@ mod tests_shapes {
         let eyev = vector(0, 0, -1);
         let normalv = vector(0, 0, -1);
         let light = PointLight::new(point(0, 0, -10),Color::new(1,1,1));
-        let result = lighting(m, light, position, eyev, normalv);
+        let result = lighting(&m, &Sphere::new(), light, position, eyev, normalv, false);
         assert_eq!(result, Color::new(1.9,1.9,1.9))
     }
 
@@ -320,7 +586,7 @@ mod tests_shapes {
         let eyev = vector(0, 2_f32.sqrt()/2_f32, -2_f32.sqrt()/2_f32);
         let normalv = vector(0, 0, -1);
         let light = PointLight::new(point(0, 0, -10),Color::new(1,1,1));
-        let result = lighting(m, light, position, eyev, normalv);
+        let result = lighting(&m, &Sphere::new(), light, position, eyev, normalv, false);
         assert_eq!(result, Color::new(1.0,1.0,1.0))
     }
 
@@ -331,7 +597,7 @@ mod tests_shapes {
         let eyev = vector(0, 0, -1);
         let normalv = vector(0, 0, -1);
         let light = PointLight::new(point(0, 10, -10),Color::new(1,1,1));
-        let result = lighting(m, light, position, eyev, normalv);
+        let result = lighting(&m, &Sphere::new(), light, position, eyev, normalv, false);
         assert_eq!(result, Color::new(0.7364,0.7364,0.7364))
     }
 
@@ -342,7 +608,7 @@ mod tests_shapes {
         let eyev = vector(0, -2_f32.sqrt()/2_f32, -2_f32.sqrt()/2_f32);
         let normalv = vector(0, 0, -1);
         let light = PointLight::new(point(0, 10, -10),Color::new(1,1,1));
-        let result = lighting(m, light, position, eyev, normalv);
+        let result = lighting(&m, &Sphere::new(), light, position, eyev, normalv, false);
         assert_eq!(result, Color::new(1.6364,1.6364,1.6364))
     }
 
@@ -353,7 +619,7 @@ mod tests_shapes {
         let eyev = vector(0, 0, -1);
         let normalv = vector(0, 0, -1);
         let light = PointLight::new(point(0, 0, 10),Color::new(1,1,1));
-        let result = lighting(m, light, position, eyev, normalv);
+        let result = lighting(&m, &Sphere::new(), light, position, eyev, normalv, false);
         assert_eq!(result, Color::new(0.1,0.1,0.1))
     }
 
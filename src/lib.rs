@@ -1,11 +1,20 @@
 pub mod point_vector;
 pub mod color;
 pub mod utils;
+pub mod error;
 pub mod canvas;
+pub mod png;
 pub mod matrix;
 pub mod transformations;
+pub mod quaternion;
 pub mod ray;
 pub mod shapes;
+pub mod bounds;
 pub mod interactions;
 pub mod point_light;
-pub mod material;
\ No newline at end of file
+pub mod material;
+pub mod world;
+pub mod camera;
+pub mod random;
+pub mod patterns;
+pub mod obj_file;
@@ -1,7 +1,8 @@
 use raytracer::point_vector::{PointVector,vector,point};
-use raytracer::canvas::Canvas;
+use raytracer::canvas::{Canvas,PpmFormat};
 use raytracer::color::Color;
-use std::fs;
+use std::fs::File;
+use std::io::{self,BufWriter};
 
 struct Projectile {
     position: PointVector,
@@ -45,7 +46,7 @@ fn tick(e: &Environment, p: Projectile) -> Projectile{
     Projectile::new(new_pos, new_velocity)
 }
 
-fn main(){
+fn main() -> io::Result<()> {
     let mut cv = Canvas::new(900,550);
     let mut p = Projectile::new(point(0,1,0), vector(1,3,0).normalize()*11.25);
     let e = Environment::new(vector(0,-0.1,0), vector(-0.01, 0, 0));
@@ -64,10 +65,8 @@ fn main(){
         let real_width = p.x().round() as usize; 
         cv.write_pixel(real_width, real_height, c);
     }
-    let ppm = cv.to_ppm();
-    fs::write("test.ppm",ppm);
-    
-
+    let file = BufWriter::new(File::create("test.ppm")?);
+    cv.write_ppm(file, PpmFormat::Ascii)
 }
 
 
@@ -0,0 +1,70 @@
+use crate::color::Color;
+use crate::patterns::Pattern;
+use crate::utils::compare_float;
+use std::sync::Arc;
+
+
+#[derive(Debug, Clone)]
+pub struct Material {
+    pub color: Color,
+    // When set, takes the place of color
+    pub pattern: Option<Arc<dyn Pattern>>,
+    pub ambient: f32,
+    pub diffuse: f32,
+    pub specular: f32,
+    pub shiness: f32,
+    pub reflective: f32,
+    pub transparency: f32,
+    pub refractive_index: f32
+}
+
+impl Default for Material {
+    fn default() -> Material {
+        Material {
+            color: Color::new(1, 1, 1),
+            pattern: None,
+            ambient: 0.1,
+            diffuse: 0.9,
+            specular: 0.9,
+            shiness: 200.0,
+            reflective: 0.0,
+            transparency: 0.0,
+            refractive_index: 1.0
+        }
+    }
+}
+
+impl PartialEq for Material {
+    fn eq(&self, other: &Self) -> bool {
+        let same_pattern = match (&self.pattern, &other.pattern) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false
+        };
+        self.color == other.color && same_pattern && compare_float(&self.ambient, &other.ambient) && compare_float(&self.diffuse, &other.diffuse)
+        && compare_float(&self.specular, &other.specular) && compare_float(&self.shiness, &other.shiness)
+        && compare_float(&self.reflective, &other.reflective) && compare_float(&self.transparency, &other.transparency)
+        && compare_float(&self.refractive_index, &other.refractive_index)
+    }
+}
+impl Eq for Material {}
+
+
+#[cfg(test)]
+mod tests_material {
+    use super::*;
+
+    #[test]
+    fn test_default() {
+        let m = Material::default();
+        assert_eq!(m.color, Color::new(1, 1, 1));
+        assert!(m.pattern.is_none());
+        assert_eq!(m.ambient, 0.1);
+        assert_eq!(m.diffuse, 0.9);
+        assert_eq!(m.specular, 0.9);
+        assert_eq!(m.shiness, 200.0);
+        assert_eq!(m.reflective, 0.0);
+        assert_eq!(m.transparency, 0.0);
+        assert_eq!(m.refractive_index, 1.0);
+    }
+}
@@ -1,6 +1,7 @@
-use std::ops::{Add,Sub,Neg,Mul,Div};
+use std::ops::Mul;
 use crate::utils::{compare_float};
 use crate::point_vector::PointVector;
+use crate::error::{Error,Result};
 
 
 
@@ -11,30 +12,27 @@ pub struct Matrix {
     j_dim: usize
 }
 fn mul_matrix_pointvector(m: &Matrix, p: &PointVector) -> PointVector {
-    let matrix_values = vec![vec![p.x], vec![p.y], vec![p.z], vec![p.w]];
-    let other_matrix = Matrix::new(matrix_values);
-    let prod_matrix = m * &other_matrix;
-    PointVector::new(prod_matrix.get(0,0), prod_matrix.get(1,0), prod_matrix.get(2,0), prod_matrix.get(3,0))
+    m.try_multiply_pointvector(p).unwrap_or_else(|e| panic!("{}", e))
 }
 
 impl Matrix {
     pub fn new(values: Vec<Vec<f32>>) -> Matrix {
+        Matrix::try_new(values).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_new(values: Vec<Vec<f32>>) -> Result<Matrix> {
         let i_dim = values.len();
-        let mut ctrl = true;
-        let mut j_dim = 0;
-        for row in values.iter() {
-            if ctrl == true {
-                j_dim = row.len();
-                ctrl = false;
-            } else {
-                if row.len() != j_dim { println!("warning")};
+        let j_dim = values.first().map_or(0, |row| row.len());
+        for (i, row) in values.iter().enumerate() {
+            if row.len() != j_dim {
+                return Err(Error::RaggedMatrix { row: i, expected: j_dim, found: row.len() })
             }
         }
-        Matrix {
+        Ok(Matrix {
             values,
             i_dim,
             j_dim
-        }
+        })
     }
 
     pub fn set(&mut self, row: usize, col: usize, value: impl Into<f64>) {
@@ -59,8 +57,7 @@ impl Matrix {
 
     }
     fn is_invertible(&self) -> bool {
-        if self.determinant() == 0.0 { false}
-        else { true}
+        self.determinant() != 0.0
     }
 
     fn cofactor_matrix(&self) -> Matrix{
@@ -81,13 +78,28 @@ impl Matrix {
     
 
     pub fn inverse(&self) -> Matrix {
-        if self.is_invertible() == false {
-            panic!("Non invertible matrix.")
+        self.try_inverse().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_inverse(&self) -> Result<Matrix> {
+        let (rows, cols) = self.shape();
+        if rows != cols {
+            return Err(Error::NotSquare { rows, cols })
+        }
+        if rows == 0 {
+            return Ok(self.clone())
+        }
+        if !self.is_invertible() {
+            return Err(Error::SingularMatrix)
+        }
+        // Cofactors of a 1x1 matrix would need the determinant of an empty one
+        if rows == 1 {
+            return Ok(Matrix::new(vec![vec![1.0 / self.get(0, 0)]]))
         }
         let mut inverse_matrix = self.cofactor_matrix();
         inverse_matrix = inverse_matrix.transpose();
         inverse_matrix = inverse_matrix.divide_by_scalar(self.determinant());
-        inverse_matrix
+        Ok(inverse_matrix)
     }
     fn submatrix(&self, row_to_remove: usize, col_to_remove: usize) -> Matrix{
         let mut submatrix_values: Vec<Vec<f32>> = vec![];
@@ -105,11 +117,11 @@ impl Matrix {
         Matrix::new(submatrix_values)
     }
 
-    fn shape(&self) -> (usize, usize) {
+    pub fn shape(&self) -> (usize, usize) {
         (self.i_dim, self.j_dim)
     }
 
-    fn get(&self, i: usize, j: usize) -> f32 {
+    pub fn get(&self, i: usize, j: usize) -> f32 {
         self.values[i][j]
     }
 
@@ -120,7 +132,10 @@ impl Matrix {
         self.get(0,0)*self.get(1,1) - self.get(1,0)*self.get(0,1)
     }
     fn determinant(&self) -> f32 {
-        if self.shape() == (2,2) {
+        if self.shape() == (1,1) {
+            self.get(0,0)
+        }
+        else if self.shape() == (2,2) {
             self.determinant_2d()
         }
         else {
@@ -131,7 +146,7 @@ impl Matrix {
     fn determinant_higher_d(&self) -> f32 {
         let mut determinant: f32 = 0.0;
         for (col,elem) in self.values[0].iter().enumerate() {
-            determinant = determinant + elem*self.cofactor(0, col);
+            determinant += elem*self.cofactor(0, col);
         }
         determinant
     }
@@ -142,9 +157,9 @@ impl Matrix {
     }
 
     fn cofactor(&self, row: usize, col: usize) -> f32 {
-        let submatrix = self.submatrix(row, col);
-        if (row+col)%2 == 0 { submatrix.determinant()}
-        else {-submatrix.determinant()}
+        let minor = self.minor(row, col);
+        if (row + col) % 2 == 0 { minor }
+        else { -minor }
     }
 
     pub fn transpose(&self) -> Matrix {
@@ -174,10 +189,14 @@ impl Matrix {
     }
 
     fn multiply(&self, other: &Matrix) -> Matrix {
+        self.try_multiply(other).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_multiply(&self, other: &Matrix) -> Result<Matrix> {
         let other_shape = other.shape();
         let this_shape = self.shape();
         if this_shape.1 != other_shape.0 {
-            panic!("Shapes are not valid.")
+            return Err(Error::ShapeMismatch { left: this_shape, right: other_shape })
         }
         let nr_rows_new_matrix = this_shape.0;
         let nr_columns_new_matrix = other_shape.1;
@@ -188,14 +207,28 @@ impl Matrix {
             for j in 0..nr_columns_new_matrix {
                 let mut value : f32 = 0.0;
                 for m in 0..inner_dimension {
-                    value = value + self.get(i,m)*other.get(m,j);
+                    value += self.get(i,m)*other.get(m,j);
                 }
                 row.push(value);
             }
             values.push(row);
         }
-        Matrix::new(values)
+        Ok(Matrix::new(values))
+    }
 
+    // Only 4x4 matrices can transform points and vectors
+    pub fn try_multiply_pointvector(&self, p: &PointVector) -> Result<PointVector> {
+        if self.shape() != (4, 4) {
+            return Err(Error::ShapeMismatch { left: self.shape(), right: (4, 1) })
+        }
+        let row = |i: usize| {
+            let mut value: f32 = 0.0;
+            for (a, b) in self.values[i].iter().zip([p.x, p.y, p.z, p.w].iter()) {
+                value += a * b;
+            }
+            value
+        };
+        Ok(PointVector::new(row(0), row(1), row(2), row(3)))
     }
 }
 
@@ -206,7 +239,7 @@ impl PartialEq for Matrix {
         } else {
             for (i, row) in self.values.iter().enumerate() {
                 for (j, value) in row.iter().enumerate() {
-                    if !compare_float(&value, &other.get(i,j)) {
+                    if !compare_float(value, &other.get(i,j)) {
                         return false
                     }
                 }
@@ -222,7 +255,7 @@ impl Mul<&Matrix> for &Matrix {
     type Output = Matrix;
 
     fn mul(self, rhs: &Matrix) -> Self::Output {
-        self.multiply(&rhs)
+        self.multiply(rhs)
     }
 }
 
@@ -235,6 +268,143 @@ impl Mul<&PointVector> for &Matrix {
     }
 }
 
+// Stack-allocated 4x4 matrix for the per-ray hot path: no allocation, f64
+// accumulation, and products with points and vectors done in place. Transforms
+// are still built as Matrix and converted once, when set on a shape, pattern or camera.
+#[derive(Debug, Clone, Copy)]
+pub struct Matrix4 {
+    values: [[f64; 4]; 4]
+}
+
+impl Matrix4 {
+    pub fn new(values: [[f64; 4]; 4]) -> Matrix4 {
+        Matrix4 {
+            values
+        }
+    }
+
+    pub fn identity() -> Matrix4 {
+        let mut values = [[0.0; 4]; 4];
+        for (i, row) in values.iter_mut().enumerate() {
+            row[i] = 1.0;
+        }
+        Matrix4::new(values)
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> f64 {
+        self.values[row][col]
+    }
+
+    pub fn transpose(&self) -> Matrix4 {
+        let mut values = [[0.0; 4]; 4];
+        for (i, row) in values.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.values[j][i];
+            }
+        }
+        Matrix4::new(values)
+    }
+
+    // Fallible version of From<&Matrix>, for matrices that may not be 4x4
+    pub fn try_from_matrix(m: &Matrix) -> Result<Matrix4> {
+        if m.shape() != (4, 4) {
+            return Err(Error::NotFourByFour { rows: m.shape().0, cols: m.shape().1 })
+        }
+        let mut values = [[0.0; 4]; 4];
+        for (i, row) in values.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = m.get(i, j) as f64;
+            }
+        }
+        Ok(Matrix4::new(values))
+    }
+
+    pub fn inverse(&self) -> Matrix4 {
+        self.try_inverse().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    // Gauss-Jordan elimination with partial pivoting
+    pub fn try_inverse(&self) -> Result<Matrix4> {
+        let mut m = self.values;
+        let mut inverse = Matrix4::identity().values;
+        for col in 0..4 {
+            let pivot_row = (col..4)
+                .max_by(|&a, &b| m[a][col].abs().partial_cmp(&m[b][col].abs()).unwrap())
+                .unwrap();
+            if m[pivot_row][col].abs() < 1e-12 {
+                return Err(Error::SingularMatrix)
+            }
+            m.swap(col, pivot_row);
+            inverse.swap(col, pivot_row);
+
+            let pivot = m[col][col];
+            for j in 0..4 {
+                m[col][j] /= pivot;
+                inverse[col][j] /= pivot;
+            }
+            for row in 0..4 {
+                let factor = m[row][col];
+                if row == col || factor == 0.0 {
+                    continue;
+                }
+                for j in 0..4 {
+                    m[row][j] -= factor * m[col][j];
+                    inverse[row][j] -= factor * inverse[col][j];
+                }
+            }
+        }
+        Ok(Matrix4::new(inverse))
+    }
+}
+
+impl PartialEq for Matrix4 {
+    fn eq(&self, other: &Self) -> bool {
+        self.values.iter().flatten().zip(other.values.iter().flatten())
+            .all(|(a, b)| compare_float(&(*a as f32), &(*b as f32)))
+    }
+}
+
+impl Mul<&Matrix4> for &Matrix4 {
+
+    type Output = Matrix4;
+
+    fn mul(self, rhs: &Matrix4) -> Self::Output {
+        let mut values = [[0.0; 4]; 4];
+        for (i, row) in values.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.values[i][k] * rhs.values[k][j]).sum();
+            }
+        }
+        Matrix4::new(values)
+    }
+}
+
+impl Mul<&PointVector> for &Matrix4 {
+
+    type Output = PointVector;
+
+    fn mul(self, rhs: &PointVector) -> Self::Output {
+        let p = [rhs.x as f64, rhs.y as f64, rhs.z as f64, rhs.w as f64];
+        let row = |i: usize| {
+            let r = &self.values[i];
+            r[0] * p[0] + r[1] * p[1] + r[2] * p[2] + r[3] * p[3]
+        };
+        PointVector::new(row(0), row(1), row(2), row(3))
+    }
+}
+
+impl From<&Matrix> for Matrix4 {
+    fn from(m: &Matrix) -> Matrix4 {
+        Matrix4::try_from_matrix(m).unwrap_or_else(|e| panic!("{}", e))
+    }
+}
+
+impl From<&Matrix4> for Matrix {
+    fn from(m: &Matrix4) -> Matrix {
+        Matrix::new(m.values.iter().map(|row| row.iter().map(|v| *v as f32).collect()).collect())
+    }
+}
+
 #[cfg(test)]
 mod tests_matrix {
     use super::*;
@@ -391,6 +561,7 @@ mod tests_matrix {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_is_invertible() {
         let m1 = Matrix::new(vec![vec![6.0, 4.0, 4.0, 4.0], vec![5.0, 5.0, 7.0, 6.0], vec![4.0, -9.0, 3.0, -7.0], vec![9.0, 1.0, 7.0, -6.0]]);
         let m2 = Matrix::new(vec![vec![-4.0, 2.0, -2.0, -3.0], vec![9.0, 6.0, 2.0, 6.0], vec![0.0, -5.0, 1.0, -5.0], vec![0.0, 0.0, 0.0, 0.0]]);
@@ -436,4 +607,159 @@ mod tests_matrix {
         assert_eq!(&c*&b.inverse(),a) 
 
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_try_new_ragged() {
+        let err = Matrix::try_new(vec![vec![1.0, 2.0], vec![3.0]]).err().unwrap();
+        assert_eq!(err, Error::RaggedMatrix { row: 1, expected: 2, found: 1 });
+        assert!(Matrix::try_new(vec![vec![1.0, 2.0], vec![3.0, 4.0]]).is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "matrix row 1 has 1 values, expected 2")]
+    fn test_new_ragged_panics() {
+        Matrix::new(vec![vec![1.0, 2.0], vec![3.0]]);
+    }
+
+    #[test]
+    fn test_try_inverse() {
+        let singular = Matrix::new(vec![vec![-4.0, 2.0, -2.0, -3.0], vec![9.0, 6.0, 2.0, 6.0], vec![0.0, -5.0, 1.0, -5.0], vec![0.0, 0.0, 0.0, 0.0]]);
+        assert_eq!(singular.try_inverse().err(), Some(Error::SingularMatrix));
+        let not_square = Matrix::new(vec![vec![1.0, 2.0, 3.0], vec![4.0, 5.0, 6.0]]);
+        assert_eq!(not_square.try_inverse().err(), Some(Error::NotSquare { rows: 2, cols: 3 }));
+        let m = Matrix::new(vec![vec![1.0, 5.0], vec![-3.0, 2.0]]);
+        assert_eq!(m.try_inverse().unwrap(), m.inverse());
+    }
+
+    #[test]
+    fn test_try_inverse_1x1() {
+        assert_eq!(Matrix::new(vec![vec![4.0]]).try_inverse(), Ok(Matrix::new(vec![vec![0.25]])));
+        assert_eq!(Matrix::new(vec![vec![0.0]]).try_inverse().err(), Some(Error::SingularMatrix));
+        assert_eq!(Matrix::new(vec![]).try_inverse(), Ok(Matrix::new(vec![])));
+    }
+
+    #[test]
+    #[should_panic(expected = "matrix is not invertible")]
+    fn test_inverse_singular_panics() {
+        Matrix::new(vec![vec![1.0, 2.0], vec![2.0, 4.0]]).inverse();
+    }
+
+    #[test]
+    fn test_try_multiply() {
+        let a = Matrix::identity(4);
+        let b = Matrix::identity(3);
+        assert_eq!(a.try_multiply(&b).err(), Some(Error::ShapeMismatch { left: (4, 4), right: (3, 3) }));
+        assert_eq!(a.try_multiply(&a).unwrap(), a);
+    }
+
+    #[test]
+    fn test_try_multiply_pointvector() {
+        let p = PointVector::new(1.0, 2.0, 3.0, 1.0);
+        assert_eq!(Matrix::identity(3).try_multiply_pointvector(&p).err(), Some(Error::ShapeMismatch { left: (3, 3), right: (4, 1) }));
+        assert_eq!(Matrix::identity(4).try_multiply_pointvector(&p), Ok(p));
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot multiply a 3x3 matrix by a 4x1 matrix")]
+    fn test_mul_pointvector_wrong_shape_panics() {
+        let _ = &Matrix::identity(3) * &PointVector::new(1.0, 2.0, 3.0, 1.0);
+    }
+}
+
+
+#[cfg(test)]
+mod tests_matrix4 {
+    use super::*;
+    use crate::point_vector::{point,vector};
+    use crate::transformations::{translate,scaling,rotation_x,rotation_y,shearing};
+    use std::f32::consts::PI;
+
+    fn sample() -> Matrix {
+        Matrix::new(vec![vec![-5.0, 2.0, 6.0, -8.0], vec![1.0, -5.0, 1.0, 8.0], vec![7.0, 7.0, -6.0, -7.0], vec![1.0, -3.0, 7.0, 4.0]])
+    }
+
+    #[test]
+    fn test_identity() {
+        assert_eq!(Matrix4::identity(), Matrix4::from(&Matrix::identity(4)));
+        assert_eq!(Matrix4::identity().get(2, 2), 1.0);
+        assert_eq!(Matrix4::identity().get(2, 3), 0.0);
+    }
+
+    #[test]
+    fn test_conversion_round_trip() {
+        let m = sample();
+        assert_eq!(Matrix::from(&Matrix4::from(&m)), m);
+    }
+
+    #[test]
+    #[should_panic(expected = "expected a 4x4 matrix, got 3x3")]
+    fn test_conversion_requires_4x4() {
+        let _ = Matrix4::from(&Matrix::identity(3));
+    }
+
+    #[test]
+    fn test_try_from_matrix() {
+        assert_eq!(Matrix4::try_from_matrix(&Matrix::identity(3)).err(), Some(Error::NotFourByFour { rows: 3, cols: 3 }));
+        assert_eq!(Matrix4::try_from_matrix(&sample()), Ok(Matrix4::from(&sample())));
+    }
+
+    #[test]
+    fn test_mul_matches_matrix() {
+        let a = sample();
+        let b = &rotation_x(0.3) * &translate(1, 2, 3);
+        assert_eq!(&Matrix4::from(&a) * &Matrix4::from(&b), Matrix4::from(&(&a * &b)));
+    }
+
+    #[test]
+    fn test_mul_point_and_vector() {
+        let m = Matrix4::from(&(&translate(5, -3, 2) * &scaling(2, 3, 4)));
+        assert_eq!(&m * &point(1, 1, 1), point(7, 0, 6));
+        // Translation doesn't move vectors
+        assert_eq!(&m * &vector(1, 1, 1), vector(2, 3, 4));
+    }
+
+    #[test]
+    fn test_transpose() {
+        let m = sample();
+        assert_eq!(Matrix4::from(&m).transpose(), Matrix4::from(&m.transpose()));
+    }
+
+    #[test]
+    fn test_inverse_matches_cofactors() {
+        let matrices = [
+            sample(),
+            Matrix::new(vec![vec![8.0, -5.0, 9.0, 2.0], vec![7.0, 5.0, 6.0, 1.0], vec![-6.0, 0.0, 9.0, 6.0], vec![-3.0, 0.0, -9.0, -4.0]]),
+            Matrix::new(vec![vec![9.0, 3.0, 0.0, 9.0], vec![-5.0, -2.0, -6.0, -3.0], vec![-4.0, 9.0, 6.0, 4.0], vec![-7.0, 6.0, 6.0, 2.0]]),
+            &(&rotation_y(PI / 5.0) * &shearing(1, 0, 0, 1, 0.5, 0)) * &scaling(0.5, 2, 3)
+        ];
+        for m in matrices.iter() {
+            assert_eq!(Matrix4::from(m).inverse(), Matrix4::from(&m.inverse()));
+            assert_eq!(&Matrix4::from(m) * &Matrix4::from(m).inverse(), Matrix4::identity());
+        }
+    }
+
+    #[test]
+    fn test_inverse_needs_pivoting() {
+        // Zero on the diagonal in the first column
+        let m = Matrix4::new([[0.0, 1.0, 0.0, 0.0], [1.0, 0.0, 0.0, 0.0], [0.0, 0.0, 2.0, 0.0], [0.0, 0.0, 0.0, 1.0]]);
+        assert_eq!(m.inverse(), Matrix4::new([[0.0, 1.0, 0.0, 0.0], [1.0, 0.0, 0.0, 0.0], [0.0, 0.0, 0.5, 0.0], [0.0, 0.0, 0.0, 1.0]]));
+    }
+
+    #[test]
+    fn test_affine_inverse_keeps_points_points() {
+        let m = Matrix4::from(&(&rotation_x(1.1) * &translate(3, -2, 7))).inverse();
+        assert!((&m * &point(0.3, 0.7, -1.9)).is_point());
+        assert!((&m * &vector(0.3, 0.7, -1.9)).is_vector());
+    }
+
+    #[test]
+    fn test_try_inverse_singular() {
+        assert_eq!(Matrix4::from(&scaling(1, 0, 1)).try_inverse().err(), Some(Error::SingularMatrix));
+    }
+
+    #[test]
+    #[should_panic(expected = "matrix is not invertible")]
+    fn test_inverse_singular() {
+        Matrix4::from(&scaling(1, 0, 1)).inverse();
+    }
+}
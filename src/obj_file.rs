@@ -0,0 +1,370 @@
+use crate::point_vector::{PointVector,point,vector};
+use crate::shapes::{Shape,Triangle,SmoothTriangle,Group};
+
+// Groups with more children than this are split into a bounding volume hierarchy
+const DIVIDE_THRESHOLD: usize = 8;
+
+
+pub struct ObjParser {
+    pub vertices: Vec<PointVector>,
+    pub normals: Vec<PointVector>,
+    pub texture_vertices: Vec<PointVector>,
+    // Faces that appear before any g statement
+    pub default_group: Vec<Box<dyn Shape>>,
+    // Named groups in the order they first appear in the file
+    pub groups: Vec<(String, Vec<Box<dyn Shape>>)>,
+    // Number of lines that were not understood and skipped
+    pub ignored: usize
+}
+
+impl ObjParser {
+    fn new() -> ObjParser {
+        ObjParser {
+            vertices: vec![],
+            normals: vec![],
+            texture_vertices: vec![],
+            default_group: vec![],
+            groups: vec![],
+            ignored: 0
+        }
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.default_group.len() + self.groups.iter().map(|(_, g)| g.len()).sum::<usize>()
+    }
+
+    pub fn group(&self, name: &str) -> Option<&[Box<dyn Shape>]> {
+        self.groups.iter().find(|(n, _)| n == name).map(|(_, g)| g.as_slice())
+    }
+
+    // Default group triangles become direct children, each named group a child group.
+    // Large meshes are then divided so rays only test the triangles near them.
+    pub fn to_group(self) -> Group {
+        let mut g = Group::new();
+        for triangle in self.default_group {
+            g.add_child(triangle);
+        }
+        for (_, triangles) in self.groups {
+            let mut named = Group::new();
+            for triangle in triangles {
+                named.add_child(triangle);
+            }
+            g.add_child(Box::new(named));
+        }
+        g.divide(DIVIDE_THRESHOLD);
+        g
+    }
+}
+
+fn parse_floats(args: &[&str]) -> Option<Vec<f32>> {
+    args.iter().map(|a| a.parse::<f32>().ok()).collect()
+}
+
+// OBJ indices start at 1, and negative ones count back from the latest record
+fn resolve_index(raw: &str, len: usize) -> Option<usize> {
+    let i = raw.parse::<i64>().ok()?;
+    let index = if i > 0 { i - 1 } else { len as i64 + i };
+    if index >= 0 && (index as usize) < len { Some(index as usize) } else { None }
+}
+
+// Face vertices may be written as v, v/vt, v//vn or v/vt/vn. Texture vertices are
+// not used yet, but a face pointing at one that doesn't exist is still rejected.
+fn parse_face_vertex(raw: &str, parser: &ObjParser) -> Option<(PointVector, Option<PointVector>)> {
+    let (vertices, normals) = (&parser.vertices, &parser.normals);
+    let mut indices = raw.split('/');
+    let vertex = resolve_index(indices.next()?, vertices.len()).map(|i| vertices[i])?;
+    if let Some(t) = indices.next() {
+        if !t.is_empty() {
+            resolve_index(t, parser.texture_vertices.len())?;
+        }
+    }
+    let normal = match indices.next() {
+        Some(n) if !n.is_empty() => Some(resolve_index(n, normals.len()).map(|i| normals[i])?),
+        _ => None
+    };
+    Some((vertex, normal))
+}
+
+// Faces where every vertex has a normal become smooth triangles
+fn fan_triangulation(face: &[(PointVector, Option<PointVector>)]) -> Vec<Box<dyn Shape>> {
+    let mut triangles: Vec<Box<dyn Shape>> = vec![];
+    for i in 1..face.len() - 1 {
+        let (p1, p2, p3) = (face[0], face[i], face[i + 1]);
+        match (p1.1, p2.1, p3.1) {
+            (Some(n1), Some(n2), Some(n3)) => triangles.push(Box::new(SmoothTriangle::new(p1.0, p2.0, p3.0, n1, n2, n3))),
+            _ => triangles.push(Box::new(Triangle::new(p1.0, p2.0, p3.0)))
+        }
+    }
+    triangles
+}
+
+pub fn parse_obj_file(contents: &str) -> ObjParser {
+    let mut parser = ObjParser::new();
+    let mut current_group: Option<usize> = None;
+
+    for line in contents.lines() {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if tokens.is_empty() {
+            continue;
+        }
+        let args = &tokens[1..];
+        match tokens[0] {
+            "v" if args.len() >= 3 => {
+                match parse_floats(&args[..3]) {
+                    Some(c) => parser.vertices.push(point(c[0], c[1], c[2])),
+                    None => parser.ignored += 1
+                }
+            },
+            "vn" if args.len() >= 3 => {
+                match parse_floats(&args[..3]) {
+                    Some(c) => parser.normals.push(vector(c[0], c[1], c[2])),
+                    None => parser.ignored += 1
+                }
+            },
+            "vt" if !args.is_empty() => {
+                match parse_floats(args) {
+                    Some(c) => parser.texture_vertices.push(point(c[0], *c.get(1).unwrap_or(&0.0), *c.get(2).unwrap_or(&0.0))),
+                    None => parser.ignored += 1
+                }
+            },
+            "f" if args.len() >= 3 => {
+                let face: Option<Vec<(PointVector, Option<PointVector>)>> = args.iter().map(|a| parse_face_vertex(a, &parser)).collect();
+                match face {
+                    Some(face) => {
+                        let triangles = fan_triangulation(&face);
+                        match &current_group {
+                            Some(i) => parser.groups[*i].1.extend(triangles),
+                            None => parser.default_group.extend(triangles)
+                        }
+                    },
+                    None => parser.ignored += 1
+                }
+            },
+            "g" if !args.is_empty() => {
+                let name = args.join(" ");
+                let index = match parser.groups.iter().position(|(n, _)| *n == name) {
+                    Some(i) => i,
+                    None => {
+                        parser.groups.push((name, vec![]));
+                        parser.groups.len() - 1
+                    }
+                };
+                current_group = Some(index);
+            },
+            _ => parser.ignored += 1
+        }
+    }
+    parser
+}
+
+
+#[cfg(test)]
+mod tests_obj_file {
+    use super::*;
+    use std::any::Any;
+
+    fn as_triangle(shape: &dyn Shape) -> &Triangle {
+        (shape as &dyn Any).downcast_ref::<Triangle>().unwrap()
+    }
+
+    fn as_smooth_triangle(shape: &dyn Shape) -> &SmoothTriangle {
+        (shape as &dyn Any).downcast_ref::<SmoothTriangle>().unwrap()
+    }
+
+    #[test]
+    fn test_ignore_unrecognized_lines() {
+        let gibberish = "There was a young lady named Bright\nwho traveled much faster than light.\nShe set out one day\nin a relative way,\nand came back the previous night.";
+        let parser = parse_obj_file(gibberish);
+        assert_eq!(parser.ignored, 5);
+    }
+
+    #[test]
+    fn test_vertex_records() {
+        let file = "v -1 1 0\nv -1.0000 0.5000 0.0000\nv 1 0 0\nv 1 1 0";
+        let parser = parse_obj_file(file);
+        assert_eq!(parser.vertices[0], point(-1, 1, 0));
+        assert_eq!(parser.vertices[1], point(-1, 0.5, 0));
+        assert_eq!(parser.vertices[2], point(1, 0, 0));
+        assert_eq!(parser.vertices[3], point(1, 1, 0));
+    }
+
+    #[test]
+    fn test_triangle_faces() {
+        let file = "v -1 1 0\nv -1 0 0\nv 1 0 0\nv 1 1 0\n\nf 1 2 3\nf 1 3 4";
+        let parser = parse_obj_file(file);
+        let t1 = as_triangle(parser.default_group[0].as_ref());
+        let t2 = as_triangle(parser.default_group[1].as_ref());
+        assert_eq!(t1.p1, parser.vertices[0]);
+        assert_eq!(t1.p2, parser.vertices[1]);
+        assert_eq!(t1.p3, parser.vertices[2]);
+        assert_eq!(t2.p1, parser.vertices[0]);
+        assert_eq!(t2.p2, parser.vertices[2]);
+        assert_eq!(t2.p3, parser.vertices[3]);
+    }
+
+    #[test]
+    fn test_triangulating_polygons() {
+        let file = "v -1 1 0\nv -1 0 0\nv 1 0 0\nv 1 1 0\nv 0 2 0\n\nf 1 2 3 4 5";
+        let parser = parse_obj_file(file);
+        assert_eq!(parser.default_group.len(), 3);
+        let t3 = as_triangle(parser.default_group[2].as_ref());
+        assert_eq!(t3.p1, parser.vertices[0]);
+        assert_eq!(t3.p2, parser.vertices[3]);
+        assert_eq!(t3.p3, parser.vertices[4]);
+    }
+
+    #[test]
+    fn test_named_groups() {
+        let file = "v -1 1 0\nv -1 0 0\nv 1 0 0\nv 1 1 0\n\ng FirstGroup\nf 1 2 3\ng SecondGroup\nf 1 3 4";
+        let parser = parse_obj_file(file);
+        let t1 = as_triangle(parser.group("FirstGroup").unwrap()[0].as_ref());
+        let t2 = as_triangle(parser.group("SecondGroup").unwrap()[0].as_ref());
+        assert_eq!(t1.p1, parser.vertices[0]);
+        assert_eq!(t1.p2, parser.vertices[1]);
+        assert_eq!(t1.p3, parser.vertices[2]);
+        assert_eq!(t2.p1, parser.vertices[0]);
+        assert_eq!(t2.p2, parser.vertices[2]);
+        assert_eq!(t2.p3, parser.vertices[3]);
+        assert_eq!(parser.triangle_count(), 2);
+    }
+
+    #[test]
+    fn test_to_group() {
+        let file = "v -1 1 0\nv -1 0 0\nv 1 0 0\nv 1 1 0\nf 1 2 3\n\ng FirstGroup\nf 1 2 3\ng SecondGroup\nf 1 3 4\nf 1 2 4";
+        let parser = parse_obj_file(file);
+        let g = parser.to_group();
+        assert_eq!(g.children().len(), 3);
+        assert!((g.children()[0].as_ref() as &dyn Any).downcast_ref::<Triangle>().is_some());
+        let second = (g.children()[2].as_ref() as &dyn Any).downcast_ref::<Group>().unwrap();
+        assert_eq!(second.children().len(), 2);
+    }
+
+    #[test]
+    fn test_to_group_divides_large_meshes() {
+        // A 10x10 grid of quads, split into 200 triangles
+        let mut file = String::new();
+        for y in 0..11 {
+            for x in 0..11 {
+                file.push_str(&format!("v {} {} 0\n", x, y));
+            }
+        }
+        for y in 0..10 {
+            for x in 0..10 {
+                let i = y * 11 + x + 1;
+                file.push_str(&format!("f {} {} {} {}\n", i, i + 1, i + 12, i + 11));
+            }
+        }
+        let g = parse_obj_file(&file).to_group();
+
+        fn check(g: &Group, depth: usize) -> (usize, usize) {
+            let mut triangles = 0;
+            let mut max_depth = depth;
+            for child in g.children() {
+                match (child.as_ref() as &dyn Any).downcast_ref::<Group>() {
+                    Some(sub) => {
+                        let (t, d) = check(sub, depth + 1);
+                        triangles += t;
+                        max_depth = max_depth.max(d);
+                    },
+                    None => triangles += 1
+                }
+            }
+            if g.children().iter().any(|c| (c.as_ref() as &dyn Any).is::<Triangle>()) {
+                assert!(g.children().len() < DIVIDE_THRESHOLD);
+            }
+            (triangles, max_depth)
+        }
+        let (triangles, depth) = check(&g, 0);
+        assert_eq!(triangles, 200);
+        assert!(depth >= 5);
+        assert_eq!(g.children().len(), 2);
+    }
+
+    #[test]
+    fn test_groups_keep_file_order() {
+        let file = "v -1 1 0\nv -1 0 0\nv 1 0 0\nv 1 1 0\ng Zeta\nf 1 2 3\ng Alpha\nf 1 3 4\nf 1 2 4\ng Zeta\nf 2 3 4";
+        let parser = parse_obj_file(file);
+        let names: Vec<&str> = parser.groups.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, vec!["Zeta", "Alpha"]);
+        assert_eq!(parser.group("Zeta").unwrap().len(), 2);
+        assert_eq!(parser.group("Alpha").unwrap().len(), 2);
+        assert!(parser.group("Missing").is_none());
+        let g = parser.to_group();
+        let first = (g.children()[0].as_ref() as &dyn Any).downcast_ref::<Group>().unwrap();
+        assert_eq!(first.children().len(), 2);
+    }
+
+    #[test]
+    fn test_vertex_normal_records() {
+        let file = "vn 0 0 1\nvn 0.707 0 -0.707\nvn 1 2 3";
+        let parser = parse_obj_file(file);
+        assert_eq!(parser.normals[0], vector(0, 0, 1));
+        assert_eq!(parser.normals[1], vector(0.707, 0, -0.707));
+        assert_eq!(parser.normals[2], vector(1, 2, 3));
+    }
+
+    #[test]
+    fn test_texture_vertex_records() {
+        let file = "vt 0.5 0.25\nvt 0 1 0.5";
+        let parser = parse_obj_file(file);
+        assert_eq!(parser.texture_vertices[0], point(0.5, 0.25, 0));
+        assert_eq!(parser.texture_vertices[1], point(0, 1, 0.5));
+    }
+
+    #[test]
+    fn test_faces_with_texture_and_normal_indices() {
+        let file = "v 0 1 0\nv -1 0 0\nv 1 0 0\nvt 0 0\nvn -1 0 0\nvn 1 0 0\nvn 0 1 0\n\nf 1//3 2//1 3//2\nf 1/1/3 2/1/1 3/1/2\nf 1/1 2/1 3/1";
+        let parser = parse_obj_file(file);
+        assert_eq!(parser.ignored, 0);
+        assert_eq!(parser.default_group.len(), 3);
+        for (index, shape) in parser.default_group.iter().enumerate() {
+            if index < 2 {
+                let t = as_smooth_triangle(shape.as_ref());
+                assert_eq!(t.p1, parser.vertices[0]);
+                assert_eq!(t.p2, parser.vertices[1]);
+                assert_eq!(t.p3, parser.vertices[2]);
+                assert_eq!(t.n1, parser.normals[2]);
+                assert_eq!(t.n2, parser.normals[0]);
+                assert_eq!(t.n3, parser.normals[1]);
+            } else {
+                let t = as_triangle(shape.as_ref());
+                assert_eq!(t.p1, parser.vertices[0]);
+                assert_eq!(t.p2, parser.vertices[1]);
+                assert_eq!(t.p3, parser.vertices[2]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_normal_index() {
+        let file = "v 0 1 0\nv -1 0 0\nv 1 0 0\nvn 0 0 1\nf 1//1 2//1 3//2";
+        let parser = parse_obj_file(file);
+        assert_eq!(parser.ignored, 1);
+        assert_eq!(parser.triangle_count(), 0);
+    }
+
+    #[test]
+    fn test_invalid_texture_index() {
+        let file = "v 0 1 0\nv -1 0 0\nv 1 0 0\nvt 0 0\nf 1/1 2/1 3/2\nf 1/0/ 2/1 3/1\nf 1/1 2/-1 3/1";
+        let parser = parse_obj_file(file);
+        assert_eq!(parser.ignored, 2);
+        assert_eq!(parser.triangle_count(), 1);
+    }
+
+    #[test]
+    fn test_negative_indices() {
+        let file = "v 0 1 0\nv -1 0 0\nv 1 0 0\nf -3 -2 -1";
+        let parser = parse_obj_file(file);
+        let t = as_triangle(parser.default_group[0].as_ref());
+        assert_eq!(t.p1, point(0, 1, 0));
+        assert_eq!(t.p3, point(1, 0, 0));
+    }
+
+    #[test]
+    fn test_invalid_face_index() {
+        let file = "v 0 1 0\nv -1 0 0\nv 1 0 0\nf 1 2 4\nf 0 1 2";
+        let parser = parse_obj_file(file);
+        assert_eq!(parser.ignored, 2);
+        assert_eq!(parser.triangle_count(), 0);
+    }
+}
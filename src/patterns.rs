@@ -0,0 +1,394 @@
+use crate::point_vector::PointVector;
+use crate::matrix::{Matrix,Matrix4};
+use crate::error::Result;
+use crate::color::Color;
+use crate::shapes::Shape;
+use std::fmt::Debug;
+
+
+// A pattern's transform with its inverse, computed once here instead of on every shading call
+#[derive(Debug, Clone)]
+pub struct PatternTransform {
+    transform: Matrix,
+    inverse: Matrix4
+}
+
+impl Default for PatternTransform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PatternTransform {
+
+    pub fn new() -> PatternTransform {
+        PatternTransform {
+            transform: Matrix::identity(4),
+            inverse: Matrix4::identity()
+        }
+    }
+
+    pub fn transform(&self) -> &Matrix {
+        &self.transform
+    }
+
+    pub fn inverse(&self) -> &Matrix4 {
+        &self.inverse
+    }
+
+    pub fn set_transform(&mut self, m: &Matrix) {
+        self.try_set_transform(m).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    // Keeps the previous transform when m isn't an invertible 4x4 matrix
+    pub fn try_set_transform(&mut self, m: &Matrix) -> Result<()> {
+        self.inverse = Matrix4::try_from_matrix(m)?.try_inverse()?;
+        self.transform = m.clone();
+        Ok(())
+    }
+
+}
+
+
+pub trait Pattern: Debug + Send + Sync {
+    // Color at a point already transformed into pattern space
+    fn pattern_at(&self, pattern_point: PointVector) -> Color;
+    fn transforms(&self) -> &PatternTransform;
+
+    fn transform(&self) -> &Matrix {
+        self.transforms().transform()
+    }
+
+    fn pattern_at_shape(&self, obj: &dyn Shape, world_point: PointVector) -> Color {
+        let object_point = obj.world_to_object(world_point);
+        let pattern_point = self.transforms().inverse() * &object_point;
+        self.pattern_at(pattern_point)
+    }
+}
+
+
+#[derive(Debug)]
+pub struct StripePattern {
+    pub a: Color,
+    pub b: Color,
+    transforms: PatternTransform
+}
+
+impl StripePattern {
+    pub fn new(a: Color, b: Color) -> StripePattern {
+        StripePattern {
+            a,
+            b,
+            transforms: PatternTransform::new()
+        }
+    }
+
+    pub fn set_transform(&mut self, m: &Matrix) {
+        self.transforms.set_transform(m)
+    }
+}
+
+impl Pattern for StripePattern {
+    fn pattern_at(&self, pattern_point: PointVector) -> Color {
+        if (pattern_point.x.floor() as i32).rem_euclid(2) == 0 { self.a }
+        else { self.b }
+    }
+
+    fn transforms(&self) -> &PatternTransform {
+        &self.transforms
+    }
+}
+
+
+#[derive(Debug)]
+pub struct GradientPattern {
+    pub a: Color,
+    pub b: Color,
+    transforms: PatternTransform
+}
+
+impl GradientPattern {
+    pub fn new(a: Color, b: Color) -> GradientPattern {
+        GradientPattern {
+            a,
+            b,
+            transforms: PatternTransform::new()
+        }
+    }
+
+    pub fn set_transform(&mut self, m: &Matrix) {
+        self.transforms.set_transform(m)
+    }
+}
+
+impl Pattern for GradientPattern {
+    fn pattern_at(&self, pattern_point: PointVector) -> Color {
+        let distance = self.b - self.a;
+        let fraction = pattern_point.x - pattern_point.x.floor();
+        self.a + distance * fraction
+    }
+
+    fn transforms(&self) -> &PatternTransform {
+        &self.transforms
+    }
+}
+
+
+#[derive(Debug)]
+pub struct RingPattern {
+    pub a: Color,
+    pub b: Color,
+    transforms: PatternTransform
+}
+
+impl RingPattern {
+    pub fn new(a: Color, b: Color) -> RingPattern {
+        RingPattern {
+            a,
+            b,
+            transforms: PatternTransform::new()
+        }
+    }
+
+    pub fn set_transform(&mut self, m: &Matrix) {
+        self.transforms.set_transform(m)
+    }
+}
+
+impl Pattern for RingPattern {
+    fn pattern_at(&self, pattern_point: PointVector) -> Color {
+        let distance = (pattern_point.x.powi(2) + pattern_point.z.powi(2)).sqrt();
+        if (distance.floor() as i32).rem_euclid(2) == 0 { self.a }
+        else { self.b }
+    }
+
+    fn transforms(&self) -> &PatternTransform {
+        &self.transforms
+    }
+}
+
+
+#[derive(Debug)]
+pub struct CheckersPattern {
+    pub a: Color,
+    pub b: Color,
+    transforms: PatternTransform
+}
+
+impl CheckersPattern {
+    pub fn new(a: Color, b: Color) -> CheckersPattern {
+        CheckersPattern {
+            a,
+            b,
+            transforms: PatternTransform::new()
+        }
+    }
+
+    pub fn set_transform(&mut self, m: &Matrix) {
+        self.transforms.set_transform(m)
+    }
+}
+
+impl Pattern for CheckersPattern {
+    fn pattern_at(&self, pattern_point: PointVector) -> Color {
+        let sum = pattern_point.x.floor() + pattern_point.y.floor() + pattern_point.z.floor();
+        if (sum as i32).rem_euclid(2) == 0 { self.a }
+        else { self.b }
+    }
+
+    fn transforms(&self) -> &PatternTransform {
+        &self.transforms
+    }
+}
+
+
+// Maps the pattern space point straight to a color, to check which space patterns are evaluated in
+#[cfg(test)]
+#[derive(Debug)]
+pub(crate) struct TestPattern {
+    transforms: PatternTransform
+}
+
+#[cfg(test)]
+impl TestPattern {
+    pub fn new() -> TestPattern {
+        TestPattern { transforms: PatternTransform::new() }
+    }
+
+    pub fn set_transform(&mut self, m: &Matrix) {
+        self.transforms.set_transform(m)
+    }
+}
+
+#[cfg(test)]
+impl Pattern for TestPattern {
+    fn pattern_at(&self, pattern_point: PointVector) -> Color {
+        Color::new(pattern_point.x, pattern_point.y, pattern_point.z)
+    }
+
+    fn transforms(&self) -> &PatternTransform {
+        &self.transforms
+    }
+}
+
+
+#[cfg(test)]
+mod tests_patterns {
+    use super::*;
+    use crate::point_vector::point;
+    use crate::shapes::Sphere;
+    use crate::transformations;
+    use crate::error::Error;
+
+    fn white() -> Color {
+        Color::new(1, 1, 1)
+    }
+
+    fn black() -> Color {
+        Color::new(0, 0, 0)
+    }
+
+    #[test]
+    fn test_stripe_new() {
+        let p = StripePattern::new(white(), black());
+        assert_eq!(p.a, white());
+        assert_eq!(p.b, black());
+        assert_eq!(*p.transform(), Matrix::identity(4));
+    }
+
+    #[test]
+    fn test_stripe_constant_in_y() {
+        let p = StripePattern::new(white(), black());
+        assert_eq!(p.pattern_at(point(0, 0, 0)), white());
+        assert_eq!(p.pattern_at(point(0, 1, 0)), white());
+        assert_eq!(p.pattern_at(point(0, 2, 0)), white());
+    }
+
+    #[test]
+    fn test_stripe_constant_in_z() {
+        let p = StripePattern::new(white(), black());
+        assert_eq!(p.pattern_at(point(0, 0, 0)), white());
+        assert_eq!(p.pattern_at(point(0, 0, 1)), white());
+        assert_eq!(p.pattern_at(point(0, 0, 2)), white());
+    }
+
+    #[test]
+    fn test_stripe_alternates_in_x() {
+        let p = StripePattern::new(white(), black());
+        assert_eq!(p.pattern_at(point(0, 0, 0)), white());
+        assert_eq!(p.pattern_at(point(0.9, 0, 0)), white());
+        assert_eq!(p.pattern_at(point(1, 0, 0)), black());
+        assert_eq!(p.pattern_at(point(-0.1, 0, 0)), black());
+        assert_eq!(p.pattern_at(point(-1, 0, 0)), black());
+        assert_eq!(p.pattern_at(point(-1.1, 0, 0)), white());
+    }
+
+    #[test]
+    fn test_pattern_object_transformation() {
+        let mut s = Sphere::new();
+        s.set_transform(&transformations::scaling(2, 2, 2));
+        let p = TestPattern::new();
+        assert_eq!(p.pattern_at_shape(&s, point(2, 3, 4)), Color::new(1, 1.5, 2));
+    }
+
+    #[test]
+    fn test_pattern_pattern_transformation() {
+        let s = Sphere::new();
+        let mut p = TestPattern::new();
+        p.set_transform(&transformations::scaling(2, 2, 2));
+        assert_eq!(p.pattern_at_shape(&s, point(2, 3, 4)), Color::new(1, 1.5, 2));
+    }
+
+    #[test]
+    fn test_pattern_both_transformations() {
+        let mut s = Sphere::new();
+        s.set_transform(&transformations::scaling(2, 2, 2));
+        let mut p = TestPattern::new();
+        p.set_transform(&transformations::translate(0.5, 1, 1.5));
+        assert_eq!(p.pattern_at_shape(&s, point(2.5, 3, 3.5)), Color::new(0.75, 0.5, 0.25));
+    }
+
+    #[test]
+    fn test_set_transform_caches_inverse() {
+        let mut p = StripePattern::new(white(), black());
+        let t = &transformations::translate(1, -2, 3) * &transformations::rotation_z(0.5);
+        p.set_transform(&t);
+        assert_eq!(*p.transform(), t);
+        assert_eq!(Matrix::from(p.transforms().inverse()), t.inverse());
+    }
+
+    #[test]
+    fn test_try_set_transform_keeps_previous_on_error() {
+        let mut p = PatternTransform::new();
+        let translation = transformations::translate(1, 2, 3);
+        p.set_transform(&translation);
+        assert_eq!(p.try_set_transform(&transformations::scaling(1, 0, 1)), Err(Error::SingularMatrix));
+        assert_eq!(*p.transform(), translation);
+        assert_eq!(Matrix::from(p.inverse()), translation.inverse());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_singular_transform_panics_on_set() {
+        let mut p = RingPattern::new(white(), black());
+        p.set_transform(&transformations::scaling(1, 0, 1));
+    }
+
+    #[test]
+    fn test_stripe_object_transformation() {
+        let mut s = Sphere::new();
+        s.set_transform(&transformations::scaling(2, 2, 2));
+        let p = StripePattern::new(white(), black());
+        assert_eq!(p.pattern_at_shape(&s, point(1.5, 0, 0)), white());
+    }
+
+    #[test]
+    fn test_stripe_pattern_transformation() {
+        let s = Sphere::new();
+        let mut p = StripePattern::new(white(), black());
+        p.set_transform(&transformations::scaling(2, 2, 2));
+        assert_eq!(p.pattern_at_shape(&s, point(1.5, 0, 0)), white());
+    }
+
+    #[test]
+    fn test_gradient() {
+        let p = GradientPattern::new(white(), black());
+        assert_eq!(p.pattern_at(point(0, 0, 0)), white());
+        assert_eq!(p.pattern_at(point(0.25, 0, 0)), Color::new(0.75, 0.75, 0.75));
+        assert_eq!(p.pattern_at(point(0.5, 0, 0)), Color::new(0.5, 0.5, 0.5));
+        assert_eq!(p.pattern_at(point(0.75, 0, 0)), Color::new(0.25, 0.25, 0.25));
+    }
+
+    #[test]
+    fn test_ring() {
+        let p = RingPattern::new(white(), black());
+        assert_eq!(p.pattern_at(point(0, 0, 0)), white());
+        assert_eq!(p.pattern_at(point(1, 0, 0)), black());
+        assert_eq!(p.pattern_at(point(0, 0, 1)), black());
+        assert_eq!(p.pattern_at(point(0.708, 0, 0.708)), black());
+    }
+
+    #[test]
+    fn test_checkers_repeat_in_x() {
+        let p = CheckersPattern::new(white(), black());
+        assert_eq!(p.pattern_at(point(0, 0, 0)), white());
+        assert_eq!(p.pattern_at(point(0.99, 0, 0)), white());
+        assert_eq!(p.pattern_at(point(1.01, 0, 0)), black());
+    }
+
+    #[test]
+    fn test_checkers_repeat_in_y() {
+        let p = CheckersPattern::new(white(), black());
+        assert_eq!(p.pattern_at(point(0, 0, 0)), white());
+        assert_eq!(p.pattern_at(point(0, 0.99, 0)), white());
+        assert_eq!(p.pattern_at(point(0, 1.01, 0)), black());
+    }
+
+    #[test]
+    fn test_checkers_repeat_in_z() {
+        let p = CheckersPattern::new(white(), black());
+        assert_eq!(p.pattern_at(point(0, 0, 0)), white());
+        assert_eq!(p.pattern_at(point(0, 0, 0.99)), white());
+        assert_eq!(p.pattern_at(point(0, 0, 1.01)), black());
+    }
+}
@@ -0,0 +1,213 @@
+use std::io::{self,Write};
+
+
+const SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+// Largest payload of a single uncompressed deflate block
+const MAX_STORED_BLOCK: usize = 65535;
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 == 1 { 0xEDB8_8320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+// CRC-32 as used by PNG chunks and zip
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut c = 0xFFFF_FFFFu32;
+    for &byte in data {
+        c = CRC_TABLE[((c ^ byte as u32) & 0xFF) as usize] ^ (c >> 8);
+    }
+    c ^ 0xFFFF_FFFF
+}
+
+// Checksum that closes every zlib stream
+pub fn adler32(data: &[u8]) -> u32 {
+    const MOD_ADLER: u32 = 65521;
+    let mut a = 1u32;
+    let mut b = 0u32;
+    // 5552 is the most bytes that can be summed before b could overflow
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MOD_ADLER;
+        b %= MOD_ADLER;
+    }
+    (b << 16) | a
+}
+
+// Wraps data in a zlib stream made of uncompressed deflate blocks. The file is
+// larger than a compressed one, but every decoder reads it.
+pub fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let blocks = data.len().div_ceil(MAX_STORED_BLOCK).max(1);
+    let mut out = Vec::with_capacity(data.len() + blocks * 5 + 6);
+    // Deflate with a 32K window, no preset dictionary; 0x7801 is a multiple of 31
+    out.extend_from_slice(&[0x78, 0x01]);
+    let mut chunks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if chunks.peek().is_none() {
+        out.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(chunk) = chunks.next() {
+        let last = chunks.peek().is_none();
+        let len = chunk.len() as u16;
+        out.push(if last { 0x01 } else { 0x00 });
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(chunk);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn write_chunk<W: Write>(w: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    w.write_all(&(data.len() as u32).to_be_bytes())?;
+    w.write_all(kind)?;
+    w.write_all(data)?;
+    let mut crc_input = Vec::with_capacity(4 + data.len());
+    crc_input.extend_from_slice(kind);
+    crc_input.extend_from_slice(data);
+    w.write_all(&crc32(&crc_input).to_be_bytes())
+}
+
+// Writes an 8-bit truecolor PNG. Each row holds width pixels of channels bytes,
+// 3 for RGB or 4 for RGBA.
+pub(crate) fn write_png<W: Write>(mut w: W, width: usize, height: usize, channels: usize, rows: impl Iterator<Item = Vec<u8>>) -> io::Result<()> {
+    let color_type = match channels {
+        3 => 2,
+        4 => 6,
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "PNG rows must be RGB or RGBA"))
+    };
+    w.write_all(&SIGNATURE)?;
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // Bit depth, color type, compression, filter and interlace methods
+    header.extend_from_slice(&[8, color_type, 0, 0, 0]);
+    write_chunk(&mut w, b"IHDR", &header)?;
+
+    let mut scanlines = Vec::with_capacity(height * (1 + width * channels));
+    for row in rows {
+        // Filter type 0: bytes are stored as they are
+        scanlines.push(0);
+        scanlines.extend_from_slice(&row);
+    }
+    write_chunk(&mut w, b"IDAT", &zlib_stored(&scanlines))?;
+    write_chunk(&mut w, b"IEND", &[])?;
+    w.flush()
+}
+
+
+#[cfg(test)]
+mod tests_png {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_adler32() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(&[255; 100_000]), 0x149A_302C);
+    }
+
+    // Reassembles the payload of a zlib stream of stored blocks
+    fn unstore(stream: &[u8]) -> Vec<u8> {
+        assert_eq!(&stream[..2], &[0x78, 0x01]);
+        assert_eq!(((stream[0] as u16) << 8 | stream[1] as u16) % 31, 0);
+        let mut data = vec![];
+        let mut pos = 2;
+        loop {
+            let last = stream[pos] & 1 == 1;
+            let len = u16::from_le_bytes([stream[pos + 1], stream[pos + 2]]);
+            let nlen = u16::from_le_bytes([stream[pos + 3], stream[pos + 4]]);
+            assert_eq!(len, !nlen);
+            pos += 5;
+            data.extend_from_slice(&stream[pos..pos + len as usize]);
+            pos += len as usize;
+            if last {
+                break;
+            }
+        }
+        assert_eq!(&stream[pos..], &adler32(&data).to_be_bytes());
+        data
+    }
+
+    #[test]
+    fn test_zlib_stored() {
+        let data: Vec<u8> = (0..150_000).map(|i| (i % 251) as u8).collect();
+        let stream = zlib_stored(&data);
+        // Three blocks of five header bytes each, plus zlib header and checksum
+        assert_eq!(stream.len(), data.len() + 3 * 5 + 6);
+        assert_eq!(unstore(&stream), data);
+    }
+
+    #[test]
+    fn test_zlib_stored_empty() {
+        assert!(unstore(&zlib_stored(&[])).is_empty());
+    }
+
+    fn chunks(png: &[u8]) -> Vec<([u8; 4], Vec<u8>)> {
+        assert_eq!(&png[..8], &SIGNATURE);
+        let mut result = vec![];
+        let mut pos = 8;
+        while pos < png.len() {
+            let len = u32::from_be_bytes([png[pos], png[pos + 1], png[pos + 2], png[pos + 3]]) as usize;
+            let kind = [png[pos + 4], png[pos + 5], png[pos + 6], png[pos + 7]];
+            let data = png[pos + 8..pos + 8 + len].to_vec();
+            let crc = &png[pos + 8 + len..pos + 12 + len];
+            assert_eq!(crc, &crc32(&png[pos + 4..pos + 8 + len]).to_be_bytes());
+            result.push((kind, data));
+            pos += 12 + len;
+        }
+        result
+    }
+
+    #[test]
+    fn test_write_png_rgb() {
+        let mut png = Vec::new();
+        let rows = vec![vec![255, 0, 0, 0, 255, 0], vec![0, 0, 255, 10, 20, 30]];
+        write_png(&mut png, 2, 2, 3, rows.into_iter()).unwrap();
+        let chunks = chunks(&png);
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(&chunks[0].0, b"IHDR");
+        assert_eq!(chunks[0].1, vec![0, 0, 0, 2, 0, 0, 0, 2, 8, 2, 0, 0, 0]);
+        assert_eq!(&chunks[1].0, b"IDAT");
+        assert_eq!(unstore(&chunks[1].1), vec![0, 255, 0, 0, 0, 255, 0, 0, 0, 0, 255, 10, 20, 30]);
+        assert_eq!(&chunks[2].0, b"IEND");
+        assert!(chunks[2].1.is_empty());
+    }
+
+    #[test]
+    fn test_write_png_rgba_color_type() {
+        let mut png = Vec::new();
+        write_png(&mut png, 1, 1, 4, vec![vec![1, 2, 3, 255]].into_iter()).unwrap();
+        let chunks = chunks(&png);
+        assert_eq!(chunks[0].1[9], 6);
+        assert_eq!(unstore(&chunks[1].1), vec![0, 1, 2, 3, 255]);
+    }
+
+    #[test]
+    fn test_write_png_rejects_other_channel_counts() {
+        let err = write_png(Vec::new(), 1, 1, 2, vec![vec![0, 0]].into_iter()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
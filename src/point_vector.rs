@@ -1,5 +1,5 @@
 use std::ops::{Add,Sub,Neg,Mul,Div};
-use crate::utils::{compare_float,assert_float_eq};
+use crate::utils::compare_float;
 
 pub fn vector(x: impl Into<f64>, y: impl Into<f64>, z: impl Into<f64>) -> PointVector{
     PointVector::new_vector(x,y,z)
@@ -46,21 +46,11 @@ impl PointVector {
         
     }
     pub fn is_point(&self) -> bool {
-        if self.w == 1.0 {
-            true
-        }
-        else  {
-            false
-        }
+        self.w == 1.0
     }
 
     pub fn is_vector(&self) -> bool {
-        if self.w == 0.0 {
-            true
-        }
-        else  {
-            false
-        }
+        self.w == 0.0
     }
 
     fn add(t1: &PointVector, t2: &PointVector) -> PointVector {
@@ -223,14 +213,17 @@ impl Eq for PointVector {}
 #[cfg(test)]
 mod tests_pointvector {
     use super::*;
+    use crate::utils::assert_float_eq;
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_compare_float() {
         assert_eq!(compare_float(&6.0,&7.0), false);
         assert_eq!(compare_float(&6.0,&6.0000000001), true);
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test1() {
         let pv = PointVector {x: 4.3,y: -4.2,z: 3.1,w: 1.0};
         assert_eq!(pv.x, 4.3);
@@ -243,6 +236,7 @@ mod tests_pointvector {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test2() {
         let pv = PointVector {x: 4.3,y: -4.2,z: 3.1,w: 0.0};
         assert_eq!(pv.x, 4.3);
@@ -265,6 +259,7 @@ mod tests_pointvector {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_new_point(){
         let v = PointVector::new_point(4.0,-4.0, 3.0);
         assert_eq!(v.x, 4.0);
@@ -276,6 +271,7 @@ mod tests_pointvector {
     }
     
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_eq(){
         let v1 = PointVector::new_point(4.0,-4.0, 3.0);
         let v2 = PointVector::new_point(4.0,-4.0, 3.0);
@@ -284,6 +280,7 @@ mod tests_pointvector {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_eq_false(){
         let v1 = PointVector::new_point(4.0,-4.0, 3.0);
         let v2 = PointVector::new_point(4.0,-3.0, 3.0);
@@ -292,6 +289,7 @@ mod tests_pointvector {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_add_pointvector() {
         let v1 = PointVector::new(3.0,-2.0,5.0,1.0);
         let v2 = PointVector {x: -2.0,y: 3.0,z: 1.0,w: 0.0};
@@ -301,6 +299,7 @@ mod tests_pointvector {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_subtract_pointvector() {
         let p1 = PointVector::new_point(3.0,2.0,1.0);
         let p2 = PointVector::new_point(5.0, 6.0,7.0);
@@ -312,6 +311,7 @@ mod tests_pointvector {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_subtract_pv_2() {
         let p = PointVector::new_point(3,2,1);
         let v = PointVector::new_vector(5,6,7);
@@ -323,6 +323,7 @@ mod tests_pointvector {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_subtract_pv_3() {
         let p = PointVector::new_vector(3,2,1);
         let v = PointVector::new_vector(5,6,7);
@@ -334,6 +335,7 @@ mod tests_pointvector {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_negate() {
         let zero = PointVector::new_vector(0,0,0);
         let v = PointVector::new_vector(1,-2,3);
@@ -345,6 +347,7 @@ mod tests_pointvector {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_negate2() {
         let v = PointVector::new(1,-2,3, -4);
         let v2 = v.negate();
@@ -353,6 +356,7 @@ mod tests_pointvector {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_negate3() {
         let v = PointVector::new(1,-2,3, -4);
         let v2 = -v;
@@ -368,6 +372,7 @@ mod tests_pointvector {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_mul_2() {
         let v = PointVector::new(1,-2,3, -4);
         assert_eq!((v*0.5)==PointVector::new(0.5,-1.0,1.5,-2.0), true);
@@ -375,6 +380,7 @@ mod tests_pointvector {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_div() {
         let v = PointVector::new(1,-2,3, -4);
         assert_eq!((v/2)==PointVector::new(0.5,-1.0,1.5,-2.0), true);
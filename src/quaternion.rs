@@ -0,0 +1,360 @@
+use std::ops::Mul;
+use crate::utils::{compare_float,EPSILON};
+use crate::point_vector::{PointVector,point,vector};
+use crate::matrix::Matrix;
+use crate::transformations::{translate,scaling};
+use crate::error::{Error,Result};
+
+
+// Unit quaternions describe rotations about any axis and interpolate smoothly,
+// without the gimbal lock of chained rotation_x/y/z
+#[derive(Debug, Clone, Copy)]
+pub struct Quaternion {
+    pub w: f32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32
+}
+
+impl Default for Quaternion {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl Quaternion {
+    pub fn new(w: impl Into<f64>, x: impl Into<f64>, y: impl Into<f64>, z: impl Into<f64>) -> Quaternion {
+        Quaternion {
+            w: w.into() as f32,
+            x: x.into() as f32,
+            y: y.into() as f32,
+            z: z.into() as f32
+        }
+    }
+
+    pub fn identity() -> Quaternion {
+        Quaternion::new(1, 0, 0, 0)
+    }
+
+    // Rotation by r radians about axis, turning the same way as rotation_x/y/z.
+    // A zero axis gives no rotation.
+    pub fn from_axis_angle(axis: PointVector, r: impl Into<f64>) -> Quaternion {
+        let length = (axis.x * axis.x + axis.y * axis.y + axis.z * axis.z).sqrt();
+        if length < EPSILON {
+            return Quaternion::identity()
+        }
+        let half = r.into() as f32 / 2.0;
+        let s = half.sin() / length;
+        Quaternion::new(half.cos(), axis.x * s, axis.y * s, axis.z * s)
+    }
+
+    // Reads the rotation from the upper 3x3 of m, which must be a pure rotation.
+    // Starts from the largest diagonal term to stay accurate near 180 degrees.
+    pub fn from_matrix(m: &Matrix) -> Quaternion {
+        let r = |i: usize, j: usize| m.get(i, j);
+        let trace = r(0, 0) + r(1, 1) + r(2, 2);
+        let q = if trace > 0.0 {
+            let s = (trace + 1.0).sqrt() * 2.0;
+            Quaternion::new(0.25 * s, (r(2, 1) - r(1, 2)) / s, (r(0, 2) - r(2, 0)) / s, (r(1, 0) - r(0, 1)) / s)
+        } else if r(0, 0) > r(1, 1) && r(0, 0) > r(2, 2) {
+            let s = (1.0 + r(0, 0) - r(1, 1) - r(2, 2)).sqrt() * 2.0;
+            Quaternion::new((r(2, 1) - r(1, 2)) / s, 0.25 * s, (r(0, 1) + r(1, 0)) / s, (r(0, 2) + r(2, 0)) / s)
+        } else if r(1, 1) > r(2, 2) {
+            let s = (1.0 + r(1, 1) - r(0, 0) - r(2, 2)).sqrt() * 2.0;
+            Quaternion::new((r(0, 2) - r(2, 0)) / s, (r(0, 1) + r(1, 0)) / s, 0.25 * s, (r(1, 2) + r(2, 1)) / s)
+        } else {
+            let s = (1.0 + r(2, 2) - r(0, 0) - r(1, 1)).sqrt() * 2.0;
+            Quaternion::new((r(1, 0) - r(0, 1)) / s, (r(0, 2) + r(2, 0)) / s, (r(1, 2) + r(2, 1)) / s, 0.25 * s)
+        };
+        q.normalize()
+    }
+
+    pub fn to_matrix(&self) -> Matrix {
+        let Quaternion { w, x, y, z } = self.normalize();
+        Matrix::new(vec![
+            vec![1.0 - 2.0 * (y * y + z * z), 2.0 * (x * y - w * z), 2.0 * (x * z + w * y), 0.0],
+            vec![2.0 * (x * y + w * z), 1.0 - 2.0 * (x * x + z * z), 2.0 * (y * z - w * x), 0.0],
+            vec![2.0 * (x * z - w * y), 2.0 * (y * z + w * x), 1.0 - 2.0 * (x * x + y * y), 0.0],
+            vec![0.0, 0.0, 0.0, 1.0]
+        ])
+    }
+
+    pub fn dot(&self, q: &Quaternion) -> f32 {
+        self.w * q.w + self.x * q.x + self.y * q.y + self.z * q.z
+    }
+
+    pub fn magnitude(&self) -> f32 {
+        self.dot(self).sqrt()
+    }
+
+    // A zero quaternion has no direction to keep, so like a zero axis it gives no rotation
+    pub fn normalize(&self) -> Quaternion {
+        let m = self.magnitude();
+        if m < EPSILON {
+            return Quaternion::identity()
+        }
+        Quaternion::new(self.w / m, self.x / m, self.y / m, self.z / m)
+    }
+
+    // Inverse rotation of a unit quaternion
+    pub fn conjugate(&self) -> Quaternion {
+        Quaternion::new(self.w, -self.x, -self.y, -self.z)
+    }
+
+    pub fn rotate(&self, p: &PointVector) -> PointVector {
+        &self.to_matrix() * p
+    }
+
+    // Constant speed interpolation along the shorter arc; t = 0 gives self and t = 1 gives q
+    pub fn slerp(&self, q: &Quaternion, t: impl Into<f64>) -> Quaternion {
+        let t = t.into() as f32;
+        let a = self.normalize();
+        let mut b = q.normalize();
+        let mut cos_theta = a.dot(&b);
+        // q and -q are the same rotation; flipping one avoids going the long way round
+        if cos_theta < 0.0 {
+            b = Quaternion::new(-b.w, -b.x, -b.y, -b.z);
+            cos_theta = -cos_theta;
+        }
+        // Nearly equal rotations would divide by a tiny sine, a straight blend is as good
+        let (ka, kb) = if cos_theta > 1.0 - EPSILON {
+            (1.0 - t, t)
+        } else {
+            let theta = cos_theta.acos();
+            let sin_theta = theta.sin();
+            (((1.0 - t) * theta).sin() / sin_theta, (t * theta).sin() / sin_theta)
+        };
+        Quaternion::new(
+            ka * a.w + kb * b.w,
+            ka * a.x + kb * b.x,
+            ka * a.y + kb * b.y,
+            ka * a.z + kb * b.z
+        ).normalize()
+    }
+}
+
+// self * q applies q first, the same order as matrix products
+impl Mul<Quaternion> for Quaternion {
+    type Output = Quaternion;
+
+    fn mul(self, q: Quaternion) -> Quaternion {
+        Quaternion::new(
+            self.w * q.w - self.x * q.x - self.y * q.y - self.z * q.z,
+            self.w * q.x + self.x * q.w + self.y * q.z - self.z * q.y,
+            self.w * q.y - self.x * q.z + self.y * q.w + self.z * q.x,
+            self.w * q.z + self.x * q.y - self.y * q.x + self.z * q.w
+        )
+    }
+}
+
+impl PartialEq for Quaternion {
+    fn eq(&self, other: &Self) -> bool {
+        compare_float(&self.w, &other.w) && compare_float(&self.x, &other.x) && compare_float(&self.y, &other.y) && compare_float(&self.z, &other.z)
+    }
+}
+
+
+// An affine transform split into translation * rotation * scaling, for keyframes.
+// The translation is the point the origin moves to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Decomposed {
+    pub translation: PointVector,
+    pub rotation: Quaternion,
+    pub scale: PointVector
+}
+
+impl Decomposed {
+    // The columns are made orthonormal with Gram-Schmidt, so the rotation is always a
+    // true rotation. Any shear is dropped and a sheared matrix doesn't come back exactly.
+    // A mirroring matrix comes back with a negative x scale.
+    pub fn try_new(m: &Matrix) -> Result<Decomposed> {
+        if m.shape() != (4, 4) {
+            return Err(Error::NotAffine)
+        }
+        let bottom = [m.get(3, 0), m.get(3, 1), m.get(3, 2), m.get(3, 3)];
+        if !bottom.iter().zip([0.0, 0.0, 0.0, 1.0].iter()).all(|(a, b)| compare_float(a, b)) {
+            return Err(Error::NotAffine)
+        }
+        let column = |j: usize| vector(m.get(0, j), m.get(1, j), m.get(2, j));
+        let mut columns = [column(0), column(1), column(2)];
+        let mirrored = (columns[0] * columns[1]).dot(&columns[2]) < 0.0;
+        let mut scale = [0.0; 3];
+        for j in 0..3 {
+            // Remove the parts along the columns already made orthonormal
+            for k in 0..j {
+                let shear = columns[j].dot(&columns[k]);
+                columns[j] = columns[j] - columns[k] * shear;
+            }
+            scale[j] = columns[j].magnitude();
+            if scale[j] < EPSILON {
+                return Err(Error::SingularMatrix)
+            }
+            columns[j] = columns[j] / scale[j];
+        }
+        if mirrored {
+            scale[0] = -scale[0];
+            columns[0] = -columns[0];
+        }
+        let mut rotation = Matrix::identity(4);
+        for (j, c) in columns.iter().enumerate() {
+            rotation.set(0, j, c.x);
+            rotation.set(1, j, c.y);
+            rotation.set(2, j, c.z);
+        }
+        Ok(Decomposed {
+            translation: point(m.get(0, 3), m.get(1, 3), m.get(2, 3)),
+            rotation: Quaternion::from_matrix(&rotation),
+            scale: vector(scale[0], scale[1], scale[2])
+        })
+    }
+
+    pub fn to_matrix(&self) -> Matrix {
+        let t = translate(self.translation.x, self.translation.y, self.translation.z);
+        let s = scaling(self.scale.x, self.scale.y, self.scale.z);
+        &(&t * &self.rotation.to_matrix()) * &s
+    }
+
+    // Linear in translation and scale, slerp in rotation
+    pub fn interpolate(&self, other: &Decomposed, t: impl Into<f64>) -> Decomposed {
+        let t = t.into() as f32;
+        Decomposed {
+            translation: self.translation + (other.translation - self.translation) * t,
+            rotation: self.rotation.slerp(&other.rotation, t),
+            scale: self.scale + (other.scale - self.scale) * t
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests_quaternion {
+    use super::*;
+    use crate::transformations::{rotation_x,rotation_y,rotation_z,shearing,Transform};
+    use std::f32::consts::{FRAC_PI_2,FRAC_PI_4,PI};
+
+    #[test]
+    fn test_axis_angle_matches_principal_rotations() {
+        assert_eq!(Quaternion::from_axis_angle(vector(1, 0, 0), 0.7).to_matrix(), rotation_x(0.7));
+        assert_eq!(Quaternion::from_axis_angle(vector(0, 1, 0), -1.2).to_matrix(), rotation_y(-1.2));
+        assert_eq!(Quaternion::from_axis_angle(vector(0, 0, 3), FRAC_PI_2).to_matrix(), rotation_z(FRAC_PI_2));
+    }
+
+    #[test]
+    fn test_zero_axis_is_identity() {
+        assert_eq!(Quaternion::from_axis_angle(vector(0, 0, 0), 1), Quaternion::identity());
+        assert_eq!(Quaternion::identity().to_matrix(), Matrix::identity(4));
+    }
+
+    #[test]
+    fn test_normalize_zero_is_identity() {
+        let zero = Quaternion::new(0, 0, 0, 0);
+        assert_eq!(zero.normalize(), Quaternion::identity());
+        assert_eq!(zero.to_matrix(), Matrix::identity(4));
+        assert_eq!(zero.slerp(&Quaternion::from_axis_angle(vector(0, 0, 1), FRAC_PI_2), 0), Quaternion::identity());
+    }
+
+    #[test]
+    fn test_rotate_point() {
+        let q = Quaternion::from_axis_angle(vector(1, 1, 1), 2.0 * PI / 3.0);
+        // A third of a turn about the diagonal cycles the axes
+        assert_eq!(q.rotate(&point(1, 0, 0)), point(0, 1, 0));
+        assert_eq!(q.conjugate().rotate(&point(0, 1, 0)), point(1, 0, 0));
+    }
+
+    #[test]
+    fn test_mul_composes_like_matrices() {
+        let a = Quaternion::from_axis_angle(vector(1, 0, 0), 0.4);
+        let b = Quaternion::from_axis_angle(vector(0, 1, 0), 1.1);
+        assert_eq!((a * b).to_matrix(), &rotation_x(0.4) * &rotation_y(1.1));
+        assert_eq!(a * a.conjugate(), Quaternion::identity());
+    }
+
+    #[test]
+    fn test_from_matrix_round_trip() {
+        let cases = [
+            Quaternion::from_axis_angle(vector(1, 2, 3), 0.5),
+            Quaternion::from_axis_angle(vector(1, 0, 0), PI),
+            Quaternion::from_axis_angle(vector(0, 1, 0), PI),
+            Quaternion::from_axis_angle(vector(0, 0, 1), PI),
+            Quaternion::from_axis_angle(vector(-1, 0.5, 2), 3.0)
+        ];
+        for q in cases.iter() {
+            let m = q.to_matrix();
+            assert_eq!(Quaternion::from_matrix(&m).to_matrix(), m);
+        }
+    }
+
+    #[test]
+    fn test_slerp() {
+        let a = Quaternion::identity();
+        let b = Quaternion::from_axis_angle(vector(0, 0, 1), FRAC_PI_2);
+        assert_eq!(a.slerp(&b, 0), a);
+        assert_eq!(a.slerp(&b, 1), b);
+        assert_eq!(a.slerp(&b, 0.5), Quaternion::from_axis_angle(vector(0, 0, 1), FRAC_PI_4));
+        assert_eq!(a.slerp(&a, 0.5), a);
+    }
+
+    #[test]
+    fn test_slerp_takes_shorter_arc() {
+        let a = Quaternion::from_axis_angle(vector(0, 1, 0), 0.1);
+        let b = Quaternion::from_axis_angle(vector(0, 1, 0), -0.1);
+        let negated_b = Quaternion::new(-b.w, -b.x, -b.y, -b.z);
+        assert_eq!(a.slerp(&negated_b, 0.5).to_matrix(), Matrix::identity(4));
+    }
+
+    #[test]
+    fn test_decompose() {
+        let q = Quaternion::from_axis_angle(vector(1, 1, 0), 0.9);
+        let m = Transform::identity().scale(2, 3, 0.5).then(&q.to_matrix()).translate(1, -2, 5);
+        let d = Decomposed::try_new(m.matrix()).unwrap();
+        assert_eq!(d.translation, point(1, -2, 5));
+        assert!(d.translation.is_point());
+        assert_eq!(d.scale, vector(2, 3, 0.5));
+        assert_eq!(d.rotation.to_matrix(), q.to_matrix());
+        assert_eq!(d.to_matrix(), *m.matrix());
+    }
+
+    #[test]
+    fn test_decompose_mirror() {
+        let m = &rotation_y(0.3) * &scaling(-1, 2, 2);
+        let d = Decomposed::try_new(&m).unwrap();
+        assert_eq!(d.scale, vector(-1, 2, 2));
+        assert_eq!(d.to_matrix(), m);
+    }
+
+    #[test]
+    fn test_decompose_errors() {
+        assert_eq!(Decomposed::try_new(&scaling(1, 0, 1)), Err(Error::SingularMatrix));
+        assert_eq!(Decomposed::try_new(&Matrix::identity(3)), Err(Error::NotAffine));
+        let mut m = Matrix::identity(4);
+        m.set(3, 0, 1);
+        assert_eq!(Decomposed::try_new(&m), Err(Error::NotAffine));
+        // Columns that span only a plane are caught after orthogonalizing
+        assert_eq!(Decomposed::try_new(&shearing(1, 0, 1, 0, 0, 0)), Err(Error::SingularMatrix));
+    }
+
+    #[test]
+    fn test_decompose_drops_shear() {
+        let d = Decomposed::try_new(&shearing(1, 0, 0, 0, 0, 0)).unwrap();
+        assert_eq!(d.rotation, Quaternion::identity());
+        assert_eq!(d.scale, vector(1, 1, 1));
+
+        let m = &rotation_x(0.6) * &(&shearing(0.5, 0.2, 0, 0.3, 0, 0) * &scaling(2, 1, 3));
+        let d = Decomposed::try_new(&m).unwrap();
+        let r = d.rotation.to_matrix();
+        assert_eq!(&r * &r.transpose(), Matrix::identity(4));
+        assert_eq!(d.rotation, Quaternion::from_axis_angle(vector(1, 0, 0), 0.6));
+    }
+
+    #[test]
+    fn test_interpolate() {
+        let a = Decomposed::try_new(&translate(0, 0, 0)).unwrap();
+        let b = Decomposed::try_new(&(&translate(10, 0, 0) * &(&rotation_z(FRAC_PI_2) * &scaling(3, 3, 3)))).unwrap();
+        let mid = a.interpolate(&b, 0.5);
+        assert_eq!(mid.translation, point(5, 0, 0));
+        assert!(mid.translation.is_point());
+        assert_eq!(mid.scale, vector(2, 2, 2));
+        assert_eq!(mid.to_matrix(), &translate(5, 0, 0) * &(&rotation_z(FRAC_PI_4) * &scaling(2, 2, 2)));
+        assert_eq!(a.interpolate(&b, 1).to_matrix(), b.to_matrix());
+    }
+}
@@ -1,5 +1,6 @@
-use crate::point_vector::{PointVector,point,vector};
+use crate::point_vector::PointVector;
 use crate::matrix::Matrix;
+use crate::error::{Error,Result};
 
 pub struct Ray {
     pub origin: PointVector,
@@ -8,12 +9,20 @@ pub struct Ray {
 
 impl Ray {
     pub fn new(origin: PointVector, direction: PointVector) -> Ray {
-        if !origin.is_point() {panic!("Origin must be a point.")}
-        if !direction.is_vector() {panic!("Direction must be a vector.")}
-        Ray {
+        Ray::try_new(origin, direction).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_new(origin: PointVector, direction: PointVector) -> Result<Ray> {
+        if !origin.is_point() {
+            return Err(Error::InvalidRayOrigin(origin))
+        }
+        if !direction.is_vector() {
+            return Err(Error::InvalidRayDirection(direction))
+        }
+        Ok(Ray {
             origin,
             direction
-        }
+        })
     }
 
     pub fn position(&self, t: impl Into<f64>) -> PointVector {
@@ -33,6 +42,7 @@ impl Ray {
 #[cfg(test)]
 mod tests_ray {
     use super::*;
+    use crate::point_vector::{point,vector};
     use crate::transformations;
 
     #[test]
@@ -44,6 +54,19 @@ mod tests_ray {
         assert_eq!(r.direction, vector(4, 5, 6));
     }
 
+    #[test]
+    fn test_try_new() {
+        assert!(Ray::try_new(point(1, 2, 3), vector(4, 5, 6)).is_ok());
+        assert_eq!(Ray::try_new(vector(1, 2, 3), vector(4, 5, 6)).err(), Some(Error::InvalidRayOrigin(vector(1, 2, 3))));
+        assert_eq!(Ray::try_new(point(1, 2, 3), point(4, 5, 6)).err(), Some(Error::InvalidRayDirection(point(4, 5, 6))));
+    }
+
+    #[test]
+    #[should_panic(expected = "ray origin must be a point")]
+    fn test_new_panics_on_vector_origin() {
+        Ray::new(vector(1, 2, 3), vector(4, 5, 6));
+    }
+
     #[test]
     fn test_position() {
         let ray = Ray::new(point(2, 3, 4), vector(1, 0, 0));
@@ -1,144 +1,2176 @@
 use crate::point_vector::{PointVector,point,vector};
-use crate::matrix::Matrix;
-use crate::ray::Ray;
+use crate::matrix::{Matrix,Matrix4};
 use crate::material::Material;
-use crate::utils::compare_float;
+use crate::ray::Ray;
+use crate::interactions::Intersection;
+use crate::bounds::BoundingBox;
+use crate::error::Result;
+use crate::utils::{compare_float,EPSILON};
+use std::fmt::Debug;
+use std::any::Any;
+
+
+// A shape's own transform and the combined transform of every group enclosing it,
+// with the inverses that intersection and shading need kept up to date on each change
+#[derive(Debug, Clone)]
+pub struct ShapeTransform {
+    transform: Matrix,
+    parent: Matrix,
+    // Matrix4 copies of the two above, converted once when each is set
+    transform4: Matrix4,
+    parent4: Matrix4,
+    inverse: Matrix4,
+    world_inverse: Matrix4,
+    normal_matrix: Matrix4
+}
+
+impl Default for ShapeTransform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShapeTransform {
+
+    pub fn new() -> ShapeTransform {
+        ShapeTransform {
+            transform: Matrix::identity(4),
+            parent: Matrix::identity(4),
+            transform4: Matrix4::identity(),
+            parent4: Matrix4::identity(),
+            inverse: Matrix4::identity(),
+            world_inverse: Matrix4::identity(),
+            normal_matrix: Matrix4::identity()
+        }
+    }
+
+    pub fn transform(&self) -> &Matrix {
+        &self.transform
+    }
+
+    pub fn parent(&self) -> &Matrix {
+        &self.parent
+    }
+
+    // Inverse of the shape's own transform
+    pub fn inverse(&self) -> &Matrix4 {
+        &self.inverse
+    }
+
+    // Inverse of parent * transform, taking world space to object space
+    pub fn world_inverse(&self) -> &Matrix4 {
+        &self.world_inverse
+    }
+
+    // Transpose of world_inverse, taking object normals to world space
+    pub fn normal_matrix(&self) -> &Matrix4 {
+        &self.normal_matrix
+    }
+
+    pub fn set_transform(&mut self, m: &Matrix) {
+        self.try_set_transform(m).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    // Keeps the previous transform when m isn't an invertible 4x4 matrix
+    pub fn try_set_transform(&mut self, m: &Matrix) -> Result<()> {
+        self.update(Matrix4::try_from_matrix(m)?, self.parent4)?;
+        self.transform = m.clone();
+        Ok(())
+    }
+
+    pub fn set_parent(&mut self, m: &Matrix) {
+        self.update(self.transform4, Matrix4::from(m)).unwrap_or_else(|e| panic!("{}", e));
+        self.parent = m.clone();
+    }
+
+    // Nothing is stored unless every inverse could be computed
+    fn update(&mut self, transform4: Matrix4, parent4: Matrix4) -> Result<()> {
+        let inverse = transform4.try_inverse()?;
+        let world_inverse = (&parent4 * &transform4).try_inverse()?;
+        self.transform4 = transform4;
+        self.parent4 = parent4;
+        self.inverse = inverse;
+        self.world_inverse = world_inverse;
+        self.normal_matrix = world_inverse.transpose();
+        Ok(())
+    }
+
+}
+
+
+pub trait Shape: Debug + Any + Send + Sync {
+    // Intersections with a ray already transformed into object space
+    fn local_intersect(&self, r: &Ray) -> Vec<Intersection<'_>>;
+    // Normal at a point already transformed into object space
+    fn local_normal_at(&self, object_point: PointVector) -> PointVector;
+    fn transforms(&self) -> &ShapeTransform;
+    fn material(&self) -> &Material;
+    // Composite shapes override this to pass the change on to their children
+    fn set_parent_transform(&mut self, m: &Matrix);
+    // Box around the shape in object space
+    fn bounds(&self) -> BoundingBox;
+
+    fn transform(&self) -> &Matrix {
+        self.transforms().transform()
+    }
+
+    // Combined transform of every group enclosing this shape
+    fn parent_transform(&self) -> &Matrix {
+        self.transforms().parent()
+    }
+
+    // Box around the shape in the space of its parent group
+    fn parent_space_bounds(&self) -> BoundingBox {
+        self.bounds().transform(self.transform())
+    }
+
+    // Regroups the children of composite shapes into a bounding volume hierarchy.
+    // Nothing calls this for you except ObjParser::to_group, so groups built by
+    // hand stay flat until divide is called on them before rendering.
+    fn divide(&mut self, _threshold: usize) {}
+
+    // Shapes that need surface data from the hit, such as u and v, override this
+    fn local_normal_at_hit(&self, object_point: PointVector, _hit: &Intersection) -> PointVector {
+        self.local_normal_at(object_point)
+    }
+
+    fn intersect(&self, r: &Ray) -> Vec<Intersection<'_>> {
+        let inverse = self.transforms().inverse();
+        let local_ray = Ray::new(inverse * &r.origin, inverse * &r.direction);
+        self.local_intersect(&local_ray)
+    }
+
+    fn world_transform(&self) -> Matrix {
+        self.parent_transform() * self.transform()
+    }
+
+    fn world_to_object(&self, world_point: PointVector) -> PointVector {
+        self.transforms().world_inverse() * &world_point
+    }
+
+    fn normal_to_world(&self, object_normal: PointVector) -> PointVector {
+        let mut world_normal = self.transforms().normal_matrix() * &object_normal;
+        world_normal.w = 0.0;
+        world_normal.normalize()
+    }
+
+    fn normal_at(&self, world_point: PointVector) -> PointVector {
+        let object_point = self.world_to_object(world_point);
+        self.normal_to_world(self.local_normal_at(object_point))
+    }
+
+    fn normal_at_hit(&self, world_point: PointVector, hit: &Intersection) -> PointVector {
+        let object_point = self.world_to_object(world_point);
+        self.normal_to_world(self.local_normal_at_hit(object_point, hit))
+    }
+
+    // Whether other is this very shape or, for composite shapes, one of its descendants
+    fn includes(&self, other: &dyn Shape) -> bool {
+        std::ptr::addr_eq(self, other)
+    }
+}
+
+
+#[derive(Debug)]
+pub struct Sphere {
+    pub center: PointVector,
+    pub material: Material,
+    transforms: ShapeTransform,
+    radius: f32 
+}
+
+impl Default for Sphere {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sphere {
+
+    pub fn new() -> Sphere {
+        Sphere{
+            center: point(0,0,0),
+            material: Material::default(),
+            transforms: ShapeTransform::new(),
+            radius: 1.0
+        }
+    }
+
+    pub fn set_transform(&mut self, m: &Matrix) {
+        self.transforms.set_transform(m)
+    }
+
+}
+
+impl PartialEq for Sphere {
+    fn eq(&self, other: &Self) -> bool {
+        compare_float(&self.radius, &other.radius) && (self.center == other.center)
+        
+    }
+}
+impl Eq for Sphere {}
+
+impl Shape for Sphere {
+    fn local_intersect(&self, r: &Ray) -> Vec<Intersection<'_>> {
+        let sphere_to_ray = r.origin - self.center;
+        let a = r.direction.dot(&r.direction);
+        let b = 2.0 * r.direction.dot(&sphere_to_ray);
+        let c = sphere_to_ray.dot(&sphere_to_ray) - 1.0;
+        let discriminant = b.powi(2) - 4.0 * a * c;
+        if discriminant < 0.0 {
+            vec![]
+        }
+        else {
+            let t1 = (-b - discriminant.sqrt()) / (2.0*a) ;
+            let t2 = (-b + discriminant.sqrt()) / (2.0*a) ;
+            let mut v = [t1,t2];
+            v.sort_by(|a,b| a.partial_cmp(b).unwrap());
+            vec![Intersection::new(v[0],self), Intersection::new(v[1],self)]
+        }
+    }
+
+    fn local_normal_at(&self, object_point: PointVector) -> PointVector {
+        (object_point - point(0, 0, 0)).normalize()
+    }
+
+    fn bounds(&self) -> BoundingBox {
+        let r = vector(self.radius, self.radius, self.radius);
+        BoundingBox::new(self.center - r, self.center + r)
+    }
+
+    fn transforms(&self) -> &ShapeTransform {
+        &self.transforms
+    }
+
+    fn set_parent_transform(&mut self, m: &Matrix) {
+        self.transforms.set_parent(m)
+    }
+
+    fn material(&self) -> &Material {
+        &self.material
+    }
+}
+
+
+#[derive(Debug)]
+pub struct Plane {
+    pub material: Material,
+    transforms: ShapeTransform
+}
+
+impl Default for Plane {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Plane {
+
+    pub fn new() -> Plane {
+        Plane {
+            material: Material::default(),
+            transforms: ShapeTransform::new()
+        }
+    }
+
+    pub fn set_transform(&mut self, m: &Matrix) {
+        self.transforms.set_transform(m)
+    }
+
+}
+
+impl Shape for Plane {
+    fn local_intersect(&self, r: &Ray) -> Vec<Intersection<'_>> {
+        // Parallel or coplanar rays never cross the xz plane
+        if r.direction.y.abs() < EPSILON {
+            return vec![]
+        }
+        let t = -r.origin.y / r.direction.y;
+        vec![Intersection::new(t, self)]
+    }
+
+    fn local_normal_at(&self, _object_point: PointVector) -> PointVector {
+        vector(0, 1, 0)
+    }
+
+    fn bounds(&self) -> BoundingBox {
+        BoundingBox::new(point(f32::NEG_INFINITY, 0, f32::NEG_INFINITY), point(f32::INFINITY, 0, f32::INFINITY))
+    }
+
+    fn transforms(&self) -> &ShapeTransform {
+        &self.transforms
+    }
+
+    fn set_parent_transform(&mut self, m: &Matrix) {
+        self.transforms.set_parent(m)
+    }
+
+    fn material(&self) -> &Material {
+        &self.material
+    }
+}
+
+
+// Entry and exit t of a ray crossing the slab between min and max along one axis
+pub(crate) fn check_axis(origin: f32, direction: f32, min: f32, max: f32) -> (f32, f32) {
+    let tmin_numerator = min - origin;
+    let tmax_numerator = max - origin;
+    let (tmin, tmax) = if direction.abs() >= EPSILON {
+        (tmin_numerator / direction, tmax_numerator / direction)
+    } else {
+        (tmin_numerator * f32::INFINITY, tmax_numerator * f32::INFINITY)
+    };
+    if tmin > tmax { (tmax, tmin) } else { (tmin, tmax) }
+}
+
+
+#[derive(Debug)]
+pub struct Cube {
+    pub material: Material,
+    transforms: ShapeTransform
+}
+
+impl Default for Cube {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Cube {
+
+    pub fn new() -> Cube {
+        Cube {
+            material: Material::default(),
+            transforms: ShapeTransform::new()
+        }
+    }
+
+    pub fn set_transform(&mut self, m: &Matrix) {
+        self.transforms.set_transform(m)
+    }
+
+}
+
+impl Shape for Cube {
+    fn local_intersect(&self, r: &Ray) -> Vec<Intersection<'_>> {
+        let (xtmin, xtmax) = check_axis(r.origin.x, r.direction.x, -1.0, 1.0);
+        let (ytmin, ytmax) = check_axis(r.origin.y, r.direction.y, -1.0, 1.0);
+        let (ztmin, ztmax) = check_axis(r.origin.z, r.direction.z, -1.0, 1.0);
+        let tmin = xtmin.max(ytmin).max(ztmin);
+        let tmax = xtmax.min(ytmax).min(ztmax);
+        if tmin > tmax {
+            return vec![]
+        }
+        vec![Intersection::new(tmin, self), Intersection::new(tmax, self)]
+    }
+
+    // The face is the one along the axis of the largest component
+    fn local_normal_at(&self, object_point: PointVector) -> PointVector {
+        let abs_x = object_point.x.abs();
+        let abs_y = object_point.y.abs();
+        let abs_z = object_point.z.abs();
+        let maxc = abs_x.max(abs_y).max(abs_z);
+        if maxc == abs_x {
+            vector(object_point.x, 0, 0)
+        } else if maxc == abs_y {
+            vector(0, object_point.y, 0)
+        } else {
+            vector(0, 0, object_point.z)
+        }
+    }
+
+    fn bounds(&self) -> BoundingBox {
+        BoundingBox::new(point(-1, -1, -1), point(1, 1, 1))
+    }
+
+    fn transforms(&self) -> &ShapeTransform {
+        &self.transforms
+    }
+
+    fn set_parent_transform(&mut self, m: &Matrix) {
+        self.transforms.set_parent(m)
+    }
+
+    fn material(&self) -> &Material {
+        &self.material
+    }
+}
+
+
+// Whether the point at t lies within radius of the y axis, for the end caps
+fn check_cap(r: &Ray, t: f32, radius: f32) -> bool {
+    let x = r.origin.x + t * r.direction.x;
+    let z = r.origin.z + t * r.direction.z;
+    (x.powi(2) + z.powi(2)) <= radius.powi(2) + EPSILON
+}
+
+
+#[derive(Debug)]
+pub struct Cylinder {
+    pub minimum: f32,
+    pub maximum: f32,
+    pub closed: bool,
+    pub material: Material,
+    transforms: ShapeTransform
+}
+
+impl Default for Cylinder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Cylinder {
+
+    pub fn new() -> Cylinder {
+        Cylinder {
+            minimum: f32::NEG_INFINITY,
+            maximum: f32::INFINITY,
+            closed: false,
+            material: Material::default(),
+            transforms: ShapeTransform::new()
+        }
+    }
+
+    pub fn set_transform(&mut self, m: &Matrix) {
+        self.transforms.set_transform(m)
+    }
+
+    fn intersect_caps<'a>(&'a self, r: &Ray, xs: &mut Vec<Intersection<'a>>) {
+        if !self.closed || r.direction.y.abs() < EPSILON {
+            return
+        }
+        let t = (self.minimum - r.origin.y) / r.direction.y;
+        if check_cap(r, t, 1.0) {
+            xs.push(Intersection::new(t, self));
+        }
+        let t = (self.maximum - r.origin.y) / r.direction.y;
+        if check_cap(r, t, 1.0) {
+            xs.push(Intersection::new(t, self));
+        }
+    }
+
+}
+
+impl Shape for Cylinder {
+    fn local_intersect(&self, r: &Ray) -> Vec<Intersection<'_>> {
+        let mut xs = vec![];
+        let a = r.direction.x.powi(2) + r.direction.z.powi(2);
+        // Rays parallel to the y axis can only hit the caps
+        if a.abs() >= EPSILON {
+            let b = 2.0 * r.origin.x * r.direction.x + 2.0 * r.origin.z * r.direction.z;
+            let c = r.origin.x.powi(2) + r.origin.z.powi(2) - 1.0;
+            let discriminant = b.powi(2) - 4.0 * a * c;
+            if discriminant < 0.0 {
+                return vec![]
+            }
+            let mut t0 = (-b - discriminant.sqrt()) / (2.0 * a);
+            let mut t1 = (-b + discriminant.sqrt()) / (2.0 * a);
+            if t0 > t1 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            for t in [t0, t1] {
+                let y = r.origin.y + t * r.direction.y;
+                if self.minimum < y && y < self.maximum {
+                    xs.push(Intersection::new(t, self));
+                }
+            }
+        }
+        self.intersect_caps(r, &mut xs);
+        xs
+    }
+
+    fn local_normal_at(&self, object_point: PointVector) -> PointVector {
+        let dist = object_point.x.powi(2) + object_point.z.powi(2);
+        if dist < 1.0 && object_point.y >= self.maximum - EPSILON {
+            vector(0, 1, 0)
+        } else if dist < 1.0 && object_point.y <= self.minimum + EPSILON {
+            vector(0, -1, 0)
+        } else {
+            vector(object_point.x, 0, object_point.z)
+        }
+    }
+
+    fn bounds(&self) -> BoundingBox {
+        BoundingBox::new(point(-1, self.minimum, -1), point(1, self.maximum, 1))
+    }
+
+    fn transforms(&self) -> &ShapeTransform {
+        &self.transforms
+    }
+
+    fn set_parent_transform(&mut self, m: &Matrix) {
+        self.transforms.set_parent(m)
+    }
+
+    fn material(&self) -> &Material {
+        &self.material
+    }
+}
+
+
+// Double-napped cone with its apex at the origin, opening along y
+#[derive(Debug)]
+pub struct Cone {
+    pub minimum: f32,
+    pub maximum: f32,
+    pub closed: bool,
+    pub material: Material,
+    transforms: ShapeTransform
+}
+
+impl Default for Cone {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Cone {
+
+    pub fn new() -> Cone {
+        Cone {
+            minimum: f32::NEG_INFINITY,
+            maximum: f32::INFINITY,
+            closed: false,
+            material: Material::default(),
+            transforms: ShapeTransform::new()
+        }
+    }
+
+    pub fn set_transform(&mut self, m: &Matrix) {
+        self.transforms.set_transform(m)
+    }
+
+    fn intersect_caps<'a>(&'a self, r: &Ray, xs: &mut Vec<Intersection<'a>>) {
+        if !self.closed || r.direction.y.abs() < EPSILON {
+            return
+        }
+        let t = (self.minimum - r.origin.y) / r.direction.y;
+        if check_cap(r, t, self.minimum.abs()) {
+            xs.push(Intersection::new(t, self));
+        }
+        let t = (self.maximum - r.origin.y) / r.direction.y;
+        if check_cap(r, t, self.maximum.abs()) {
+            xs.push(Intersection::new(t, self));
+        }
+    }
+
+}
+
+impl Shape for Cone {
+    fn local_intersect(&self, r: &Ray) -> Vec<Intersection<'_>> {
+        let mut xs = vec![];
+        let a = r.direction.x.powi(2) - r.direction.y.powi(2) + r.direction.z.powi(2);
+        let b = 2.0 * r.origin.x * r.direction.x - 2.0 * r.origin.y * r.direction.y + 2.0 * r.origin.z * r.direction.z;
+        let c = r.origin.x.powi(2) - r.origin.y.powi(2) + r.origin.z.powi(2);
+        let mut ts = vec![];
+        if a.abs() < EPSILON {
+            // Parallel to one of the halves, so it crosses the other at most once
+            if b.abs() >= EPSILON {
+                ts.push(-c / (2.0 * b));
+            }
+        } else {
+            let mut discriminant = b.powi(2) - 4.0 * a * c;
+            // Tangent rays land a hair below zero in f32
+            if discriminant.abs() < EPSILON {
+                discriminant = 0.0;
+            }
+            if discriminant < 0.0 {
+                return vec![]
+            }
+            let mut t0 = (-b - discriminant.sqrt()) / (2.0 * a);
+            let mut t1 = (-b + discriminant.sqrt()) / (2.0 * a);
+            if t0 > t1 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            ts.push(t0);
+            ts.push(t1);
+        }
+        for t in ts {
+            let y = r.origin.y + t * r.direction.y;
+            if self.minimum < y && y < self.maximum {
+                xs.push(Intersection::new(t, self));
+            }
+        }
+        self.intersect_caps(r, &mut xs);
+        xs
+    }
+
+    fn local_normal_at(&self, object_point: PointVector) -> PointVector {
+        let dist = object_point.x.powi(2) + object_point.z.powi(2);
+        if dist < object_point.y.powi(2) && object_point.y >= self.maximum - EPSILON {
+            vector(0, 1, 0)
+        } else if dist < object_point.y.powi(2) && object_point.y <= self.minimum + EPSILON {
+            vector(0, -1, 0)
+        } else {
+            let mut y = dist.sqrt();
+            if object_point.y > 0.0 {
+                y = -y;
+            }
+            vector(object_point.x, y, object_point.z)
+        }
+    }
+
+    fn bounds(&self) -> BoundingBox {
+        let limit = self.minimum.abs().max(self.maximum.abs());
+        BoundingBox::new(point(-limit, self.minimum, -limit), point(limit, self.maximum, limit))
+    }
+
+    fn transforms(&self) -> &ShapeTransform {
+        &self.transforms
+    }
+
+    fn set_parent_transform(&mut self, m: &Matrix) {
+        self.transforms.set_parent(m)
+    }
+
+    fn material(&self) -> &Material {
+        &self.material
+    }
+}
+
+
+#[derive(Debug)]
+pub struct Triangle {
+    pub p1: PointVector,
+    pub p2: PointVector,
+    pub p3: PointVector,
+    pub e1: PointVector,
+    pub e2: PointVector,
+    pub normal: PointVector,
+    pub material: Material,
+    transforms: ShapeTransform
+}
+
+impl Triangle {
+
+    pub fn new(p1: PointVector, p2: PointVector, p3: PointVector) -> Triangle {
+        let e1 = p2 - p1;
+        let e2 = p3 - p1;
+        Triangle {
+            p1,
+            p2,
+            p3,
+            e1,
+            e2,
+            normal: (e2 * e1).normalize(),
+            material: Material::default(),
+            transforms: ShapeTransform::new()
+        }
+    }
+
+    pub fn set_transform(&mut self, m: &Matrix) {
+        self.transforms.set_transform(m)
+    }
+
+}
+
+// Möller–Trumbore, giving t along with the barycentric u and v of the hit
+fn intersect_triangle(p1: PointVector, e1: PointVector, e2: PointVector, r: &Ray) -> Option<(f32, f32, f32)> {
+    let dir_cross_e2 = r.direction * e2;
+    let det = e1.dot(&dir_cross_e2);
+    if det.abs() < EPSILON {
+        return None
+    }
+    let f = 1.0 / det;
+    let p1_to_origin = r.origin - p1;
+    let u = f * p1_to_origin.dot(&dir_cross_e2);
+    if !(0.0..=1.0).contains(&u) {
+        return None
+    }
+    let origin_cross_e1 = p1_to_origin * e1;
+    let v = f * r.direction.dot(&origin_cross_e1);
+    if v < 0.0 || (u + v) > 1.0 {
+        return None
+    }
+    let t = f * e2.dot(&origin_cross_e1);
+    Some((t, u, v))
+}
+
+impl Shape for Triangle {
+    fn local_intersect(&self, r: &Ray) -> Vec<Intersection<'_>> {
+        match intersect_triangle(self.p1, self.e1, self.e2, r) {
+            Some((t, u, v)) => vec![Intersection::new_with_uv(t, self, u, v)],
+            None => vec![]
+        }
+    }
+
+    fn local_normal_at(&self, _object_point: PointVector) -> PointVector {
+        self.normal
+    }
+
+    fn bounds(&self) -> BoundingBox {
+        let mut b = BoundingBox::empty();
+        b.add_point(self.p1);
+        b.add_point(self.p2);
+        b.add_point(self.p3);
+        b
+    }
+
+    fn transforms(&self) -> &ShapeTransform {
+        &self.transforms
+    }
+
+    fn set_parent_transform(&mut self, m: &Matrix) {
+        self.transforms.set_parent(m)
+    }
+
+    fn material(&self) -> &Material {
+        &self.material
+    }
+}
+
+
+#[derive(Debug)]
+pub struct SmoothTriangle {
+    pub p1: PointVector,
+    pub p2: PointVector,
+    pub p3: PointVector,
+    pub n1: PointVector,
+    pub n2: PointVector,
+    pub n3: PointVector,
+    pub e1: PointVector,
+    pub e2: PointVector,
+    pub material: Material,
+    transforms: ShapeTransform
+}
+
+impl SmoothTriangle {
+
+    pub fn new(p1: PointVector, p2: PointVector, p3: PointVector, n1: PointVector, n2: PointVector, n3: PointVector) -> SmoothTriangle {
+        SmoothTriangle {
+            p1,
+            p2,
+            p3,
+            n1,
+            n2,
+            n3,
+            e1: p2 - p1,
+            e2: p3 - p1,
+            material: Material::default(),
+            transforms: ShapeTransform::new()
+        }
+    }
+
+    pub fn set_transform(&mut self, m: &Matrix) {
+        self.transforms.set_transform(m)
+    }
+
+    fn interpolate_normal(&self, u: f32, v: f32) -> PointVector {
+        self.n2 * u + self.n3 * v + self.n1 * (1.0 - u - v)
+    }
+
+}
+
+impl Shape for SmoothTriangle {
+    fn local_intersect(&self, r: &Ray) -> Vec<Intersection<'_>> {
+        match intersect_triangle(self.p1, self.e1, self.e2, r) {
+            Some((t, u, v)) => vec![Intersection::new_with_uv(t, self, u, v)],
+            None => vec![]
+        }
+    }
+
+    // Without a hit, u and v are recovered from the point itself
+    fn local_normal_at(&self, object_point: PointVector) -> PointVector {
+        let p = object_point - self.p1;
+        let d00 = self.e1.dot(&self.e1);
+        let d01 = self.e1.dot(&self.e2);
+        let d11 = self.e2.dot(&self.e2);
+        let d20 = p.dot(&self.e1);
+        let d21 = p.dot(&self.e2);
+        let denom = d00 * d11 - d01 * d01;
+        let u = (d11 * d20 - d01 * d21) / denom;
+        let v = (d00 * d21 - d01 * d20) / denom;
+        self.interpolate_normal(u, v)
+    }
+
+    fn local_normal_at_hit(&self, _object_point: PointVector, hit: &Intersection) -> PointVector {
+        self.interpolate_normal(hit.u, hit.v)
+    }
+
+    fn bounds(&self) -> BoundingBox {
+        let mut b = BoundingBox::empty();
+        b.add_point(self.p1);
+        b.add_point(self.p2);
+        b.add_point(self.p3);
+        b
+    }
+
+    fn transforms(&self) -> &ShapeTransform {
+        &self.transforms
+    }
+
+    fn set_parent_transform(&mut self, m: &Matrix) {
+        self.transforms.set_parent(m)
+    }
+
+    fn material(&self) -> &Material {
+        &self.material
+    }
+}
+
+
+type Children = Vec<Box<dyn Shape>>;
+
+#[derive(Debug)]
+pub struct Group {
+    material: Material,
+    transforms: ShapeTransform,
+    children: Children,
+    bounds: BoundingBox
+}
+
+impl Default for Group {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Group {
+
+    pub fn new() -> Group {
+        Group {
+            material: Material::default(),
+            transforms: ShapeTransform::new(),
+            children: vec![],
+            bounds: BoundingBox::empty()
+        }
+    }
+
+    pub fn set_transform(&mut self, m: &Matrix) {
+        self.transforms.set_transform(m);
+        self.update_children();
+    }
+
+    pub fn add_child(&mut self, mut child: Box<dyn Shape>) {
+        child.set_parent_transform(&self.world_transform());
+        self.bounds.merge(&child.parent_space_bounds());
+        self.children.push(child);
+    }
+
+    pub fn children(&self) -> &[Box<dyn Shape>] {
+        &self.children
+    }
+
+    // Children hold the composed transform of every group above them
+    fn update_children(&mut self) {
+        let world_transform = self.world_transform();
+        for child in self.children.iter_mut() {
+            child.set_parent_transform(&world_transform);
+        }
+    }
+
+    fn make_subgroup(&mut self, children: Children) {
+        let mut subgroup = Group::new();
+        subgroup.set_parent_transform(&self.world_transform());
+        for child in children {
+            subgroup.add_child(child);
+        }
+        self.add_child(Box::new(subgroup));
+    }
+
+    // Splits the bounded children in two at the cut that minimises the surface
+    // area heuristic, trying every position along each axis. Unbounded children,
+    // such as planes, are left in this group, and so are the bounded ones when no
+    // cut is cheaper than testing every child against this group's box.
+    fn partition_children(&mut self) -> Option<(Children, Children)> {
+        let (mut bounded, unbounded): (Vec<_>, Vec<_>) = self.children.drain(..)
+            .map(|child| (child.parent_space_bounds(), child))
+            .partition(|(b, _)| b.is_finite());
+        self.children = unbounded.into_iter().map(|(_, child)| child).collect();
+        if bounded.len() < 2 {
+            self.children.extend(bounded.into_iter().map(|(_, child)| child));
+            return None
+        }
+
+        let n = bounded.len();
+        let mut best: Option<(f32, usize, usize)> = None;
+        for axis in 0..3 {
+            sort_by_centroid(&mut bounded, axis);
+            let mut right_areas = vec![0.0; n];
+            let mut right = BoundingBox::empty();
+            for i in (1..n).rev() {
+                right.merge(&bounded[i].0);
+                right_areas[i] = right.surface_area();
+            }
+            let mut left = BoundingBox::empty();
+            for i in 1..n {
+                left.merge(&bounded[i - 1].0);
+                let cost = left.surface_area() * i as f32 + right_areas[i] * (n - i) as f32;
+                if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                    best = Some((cost, axis, i));
+                }
+            }
+        }
+
+        let mut parent = BoundingBox::empty();
+        for (b, _) in bounded.iter() {
+            parent.merge(b);
+        }
+        let leaf_cost = parent.surface_area() * n as f32;
+        let (cost, axis, split) = best.unwrap();
+        if cost >= leaf_cost {
+            self.children.extend(bounded.into_iter().map(|(_, child)| child));
+            return None
+        }
+        sort_by_centroid(&mut bounded, axis);
+        let right = bounded.split_off(split);
+        Some((
+            bounded.into_iter().map(|(_, child)| child).collect(),
+            right.into_iter().map(|(_, child)| child).collect()
+        ))
+    }
+
+}
+
+fn sort_by_centroid(shapes: &mut [(BoundingBox, Box<dyn Shape>)], axis: usize) {
+    let key = |b: &BoundingBox| {
+        let c = b.centroid();
+        match axis {
+            0 => c.x,
+            1 => c.y,
+            _ => c.z
+        }
+    };
+    shapes.sort_by(|(a, _), (b, _)| key(a).partial_cmp(&key(b)).unwrap());
+}
+
+impl Shape for Group {
+    fn local_intersect(&self, r: &Ray) -> Vec<Intersection<'_>> {
+        if !self.bounds.intersects(r) {
+            return vec![]
+        }
+        let mut xs = vec![];
+        for child in self.children.iter() {
+            xs.extend(child.intersect(r));
+        }
+        xs.sort_by(|a, b| a.t.partial_cmp(&b.t).unwrap());
+        xs
+    }
+
+    fn local_normal_at(&self, _object_point: PointVector) -> PointVector {
+        panic!("Groups have no surface; normals come from their children.")
+    }
+
+    fn bounds(&self) -> BoundingBox {
+        self.bounds
+    }
+
+    fn transforms(&self) -> &ShapeTransform {
+        &self.transforms
+    }
+
+    fn set_parent_transform(&mut self, m: &Matrix) {
+        self.transforms.set_parent(m);
+        self.update_children();
+    }
+
+    fn material(&self) -> &Material {
+        &self.material
+    }
+
+    fn includes(&self, other: &dyn Shape) -> bool {
+        self.children.iter().any(|child| child.includes(other))
+    }
+
+    fn divide(&mut self, threshold: usize) {
+        if threshold <= self.children.len() {
+            if let Some((left, right)) = self.partition_children() {
+                self.make_subgroup(left);
+                self.make_subgroup(right);
+            }
+        }
+        for child in self.children.iter_mut() {
+            child.divide(threshold);
+        }
+    }
+}
+
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum CsgOperation {
+    Union,
+    Intersection,
+    Difference
+}
+
+// lhit: the hit is on the left shape; inl/inr: the hit is inside the left/right shape
+pub fn intersection_allowed(op: CsgOperation, lhit: bool, inl: bool, inr: bool) -> bool {
+    match op {
+        CsgOperation::Union => (lhit && !inr) || (!lhit && !inl),
+        CsgOperation::Intersection => (lhit && inr) || (!lhit && inl),
+        CsgOperation::Difference => (lhit && !inr) || (!lhit && inl)
+    }
+}
+
+#[derive(Debug)]
+pub struct Csg {
+    pub operation: CsgOperation,
+    left: Box<dyn Shape>,
+    right: Box<dyn Shape>,
+    material: Material,
+    transforms: ShapeTransform,
+    bounds: BoundingBox
+}
+
+impl Csg {
+
+    pub fn new(operation: CsgOperation, left: Box<dyn Shape>, right: Box<dyn Shape>) -> Csg {
+        let mut csg = Csg {
+            operation,
+            left,
+            right,
+            material: Material::default(),
+            transforms: ShapeTransform::new(),
+            bounds: BoundingBox::empty()
+        };
+        csg.bounds.merge(&csg.left.parent_space_bounds());
+        csg.bounds.merge(&csg.right.parent_space_bounds());
+        csg.update_children();
+        csg
+    }
+
+    pub fn set_transform(&mut self, m: &Matrix) {
+        self.transforms.set_transform(m);
+        self.update_children();
+    }
+
+    pub fn left(&self) -> &dyn Shape {
+        self.left.as_ref()
+    }
+
+    pub fn right(&self) -> &dyn Shape {
+        self.right.as_ref()
+    }
+
+    fn update_children(&mut self) {
+        let world_transform = self.world_transform();
+        self.left.set_parent_transform(&world_transform);
+        self.right.set_parent_transform(&world_transform);
+    }
+
+    // Expects xs sorted by t
+    pub fn filter_intersections<'a>(&self, xs: Vec<Intersection<'a>>) -> Vec<Intersection<'a>> {
+        let mut inl = false;
+        let mut inr = false;
+        let mut result = vec![];
+        for i in xs {
+            let lhit = self.left.includes(i.obj);
+            if intersection_allowed(self.operation, lhit, inl, inr) {
+                result.push(i);
+            }
+            if lhit {
+                inl = !inl;
+            } else {
+                inr = !inr;
+            }
+        }
+        result
+    }
+
+}
+
+impl Shape for Csg {
+    fn local_intersect(&self, r: &Ray) -> Vec<Intersection<'_>> {
+        if !self.bounds.intersects(r) {
+            return vec![]
+        }
+        let mut xs = self.left.intersect(r);
+        xs.extend(self.right.intersect(r));
+        xs.sort_by(|a, b| a.t.partial_cmp(&b.t).unwrap());
+        self.filter_intersections(xs)
+    }
+
+    fn local_normal_at(&self, _object_point: PointVector) -> PointVector {
+        panic!("CSG shapes have no surface; normals come from their children.")
+    }
+
+    fn bounds(&self) -> BoundingBox {
+        self.bounds
+    }
+
+    fn transforms(&self) -> &ShapeTransform {
+        &self.transforms
+    }
+
+    fn set_parent_transform(&mut self, m: &Matrix) {
+        self.transforms.set_parent(m);
+        self.update_children();
+    }
+
+    fn material(&self) -> &Material {
+        &self.material
+    }
+
+    fn includes(&self, other: &dyn Shape) -> bool {
+        self.left.includes(other) || self.right.includes(other)
+    }
+
+    fn divide(&mut self, threshold: usize) {
+        self.left.divide(threshold);
+        self.right.divide(threshold);
+    }
+}
+
+
+#[cfg(test)]
+mod tests_sphere {
+    use super::*;
+    use crate::transformations;
+    use crate::error::Error;
+
+    #[test]
+    fn test_transformation() {
+        let s = Sphere::new();
+        assert_eq!(*s.transform(), Matrix::identity(4));
+
+    }
+
+    #[test]
+    fn test_transformation2() {
+        let mut s = Sphere::new();
+        let t = transformations::translate(2, 3, 4);
+        s.set_transform(&t);
+        assert_eq!(*s.transform(), t)
+
+    }
+    #[test]
+    fn test_normal_at() {
+        let s = Sphere::new();
+        let n = s.normal_at(point(1,0,0));
+        assert_eq!(n, vector(1, 0, 0));
+    }
+    #[test]
+    fn test_normal_at2() {
+        let s = Sphere::new();
+        let n = s.normal_at(point(0,1,0));
+        assert_eq!(n, vector(0, 1, 0));
+    }
+    #[test]
+    fn test_normal_at3() {
+        let s = Sphere::new();
+        let n = s.normal_at(point(0,0,1));
+        assert_eq!(n, vector(0, 0, 1));
+    }
+    #[test]
+    fn test_normal_at4() {
+        let s = Sphere::new();
+        let c = 3.0_f32.sqrt()/3.0;
+        let n = s.normal_at(point(c,c,c));
+        assert_eq!(n, vector(c, c, c));
+    }
+
+    #[test]
+    fn test_normal_at5() {
+        let s = Sphere::new();
+        let c = 3.0_f32.sqrt()/3.0;
+        let n = s.normal_at(point(c,c,c));
+        assert_eq!(n, n.normalize());
+    }
+
+    #[test]
+    #[allow(clippy::approx_constant)]
+    fn test_normal_at6() {
+        let mut s = Sphere::new();
+        s.set_transform(&transformations::translate(0, 1, 0));
+        let n = s.normal_at(point(0,1.70711,-0.70711));
+        assert_eq!(n, vector(0, 0.70711, -0.70711));
+    }
+
+    #[test]
+    fn test_normal_at7() {
+        let mut s = Sphere::new();
+        let m = &transformations::scaling(1, 0.5, 1) * &transformations::rotation_z(std::f32::consts::PI/5.0);
+        s.set_transform(&m);
+        let y = 2.0_f32.sqrt()/2.0;
+        let z = -y;
+        let n = s.normal_at(point(0,y,z));
+        assert_eq!(n, vector(0, 0.97014, -0.24254));
+    }
+
+    #[test]
+    fn test_shape_intersect_transformed() {
+        let mut s = Sphere::new();
+        s.set_transform(&transformations::scaling(2, 2, 2));
+        let shape: &dyn Shape = &s;
+        let r = Ray::new(point(0, 0, -5), vector(0, 0, 1));
+        let xs = shape.intersect(&r);
+        assert_eq!(xs.len(), 2);
+        assert_eq!(xs[0].t, 3.0);
+        assert_eq!(xs[1].t, 7.0);
+        assert!(std::ptr::addr_eq(xs[0].obj, shape));
+    }
+
+    #[test]
+    fn test_shape_local_normal_at() {
+        let s = Sphere::new();
+        assert_eq!(s.local_normal_at(point(0, 0, -1)), vector(0, 0, -1));
+    }
+
+    #[test]
+    fn test_sphere_material() {
+        let s = Sphere::new();
+        let m = &s.material;
+        assert_eq!(*m, Material::default());
+    }
+
+    #[test]
+    #[allow(clippy::field_reassign_with_default)]
+    fn test_sphere_material2() {
+        let mut s = Sphere::new();
+        let mut m = Material::default();
+        m.ambient = 1.0;
+        s.material = m.clone();
+        assert_eq!(s.material,m);
+    }
+
+    #[test]
+    fn test_bounds() {
+        let b = Sphere::new().bounds();
+        assert_eq!(b.min, point(-1, -1, -1));
+        assert_eq!(b.max, point(1, 1, 1));
+    }
+
+    #[test]
+    fn test_parent_space_bounds() {
+        let mut s = Sphere::new();
+        s.set_transform(&(&transformations::translate(1, -3, 5) * &transformations::scaling(0.5, 2, 4)));
+        let b = s.parent_space_bounds();
+        assert_eq!(b.min, point(0.5, -5, 1));
+        assert_eq!(b.max, point(1.5, -1, 9));
+    }
+
+    #[test]
+    fn test_set_transform_caches_inverses() {
+        let mut s = Sphere::new();
+        let t = &transformations::translate(1, -2, 3) * &transformations::rotation_z(0.5);
+        s.set_transform(&t);
+        let cached = s.transforms();
+        assert_eq!(Matrix::from(cached.inverse()), t.inverse());
+        assert_eq!(Matrix::from(cached.world_inverse()), t.inverse());
+        assert_eq!(Matrix::from(cached.normal_matrix()), t.inverse().transpose());
+    }
+
+    #[test]
+    fn test_set_parent_transform_updates_world_inverse() {
+        let mut s = Sphere::new();
+        let t = transformations::scaling(1, 2, 3);
+        let parent = transformations::rotation_y(0.7);
+        s.set_transform(&t);
+        s.set_parent_transform(&parent);
+        let world = &parent * &t;
+        assert_eq!(Matrix::from(s.transforms().inverse()), t.inverse());
+        assert_eq!(Matrix::from(s.transforms().world_inverse()), world.inverse());
+        assert_eq!(Matrix::from(s.transforms().normal_matrix()), world.inverse().transpose());
+    }
+
+    #[test]
+    fn test_try_set_transform_keeps_previous_on_error() {
+        let mut t = ShapeTransform::new();
+        let translation = transformations::translate(1, 2, 3);
+        t.set_transform(&translation);
+        assert_eq!(t.try_set_transform(&transformations::scaling(1, 0, 1)), Err(Error::SingularMatrix));
+        assert_eq!(t.try_set_transform(&Matrix::identity(3)), Err(Error::NotFourByFour { rows: 3, cols: 3 }));
+        assert_eq!(*t.transform(), translation);
+        assert_eq!(Matrix::from(t.inverse()), translation.inverse());
+        assert_eq!(Matrix::from(t.world_inverse()), translation.inverse());
+    }
+}
+
+#[cfg(test)]
+mod tests_plane {
+    use super::*;
+    use crate::transformations;
+
+    #[test]
+    fn test_normal_is_constant() {
+        let p = Plane::new();
+        assert_eq!(p.local_normal_at(point(0, 0, 0)), vector(0, 1, 0));
+        assert_eq!(p.local_normal_at(point(10, 0, -10)), vector(0, 1, 0));
+        assert_eq!(p.local_normal_at(point(-5, 0, 150)), vector(0, 1, 0));
+    }
+
+    #[test]
+    fn test_intersect_parallel() {
+        let p = Plane::new();
+        let r = Ray::new(point(0, 10, 0), vector(0, 0, 1));
+        assert_eq!(p.local_intersect(&r).len(), 0);
+    }
+
+    #[test]
+    fn test_intersect_coplanar() {
+        let p = Plane::new();
+        let r = Ray::new(point(0, 0, 0), vector(0, 0, 1));
+        assert_eq!(p.local_intersect(&r).len(), 0);
+    }
+
+    #[test]
+    fn test_intersect_from_above() {
+        let p = Plane::new();
+        let r = Ray::new(point(0, 1, 0), vector(0, -1, 0));
+        let xs = p.local_intersect(&r);
+        assert_eq!(xs.len(), 1);
+        assert_eq!(xs[0].t, 1.0);
+        assert!(std::ptr::addr_eq(xs[0].obj, &p));
+    }
+
+    #[test]
+    fn test_intersect_from_below() {
+        let p = Plane::new();
+        let r = Ray::new(point(0, -1, 0), vector(0, 1, 0));
+        let xs = p.local_intersect(&r);
+        assert_eq!(xs.len(), 1);
+        assert_eq!(xs[0].t, 1.0);
+    }
+
+    #[test]
+    fn test_transformed_plane() {
+        let mut p = Plane::new();
+        p.set_transform(&transformations::rotation_z(std::f32::consts::PI / 2.0));
+        let r = Ray::new(point(-3, 0, 0), vector(1, 0, 0));
+        let xs = p.intersect(&r);
+        assert_eq!(xs.len(), 1);
+        assert_eq!(xs[0].t, 3.0);
+        assert_eq!(p.normal_at(point(0, 1, 0)), vector(-1, 0, 0));
+    }
+
+    #[test]
+    fn test_bounds() {
+        let b = Plane::new().bounds();
+        assert_eq!(b.min.x, f32::NEG_INFINITY);
+        assert_eq!(b.max.z, f32::INFINITY);
+        assert_eq!(b.min.y, 0.0);
+        assert_eq!(b.max.y, 0.0);
+        assert!(!b.is_finite());
+    }
+}
+
+#[cfg(test)]
+mod tests_cube {
+    use super::*;
+    use crate::transformations;
+
+    #[test]
+    fn test_ray_intersects() {
+        let c = Cube::new();
+        let cases = [
+            (point(5, 0.5, 0), vector(-1, 0, 0), 4.0, 6.0),
+            (point(-5, 0.5, 0), vector(1, 0, 0), 4.0, 6.0),
+            (point(0.5, 5, 0), vector(0, -1, 0), 4.0, 6.0),
+            (point(0.5, -5, 0), vector(0, 1, 0), 4.0, 6.0),
+            (point(0.5, 0, 5), vector(0, 0, -1), 4.0, 6.0),
+            (point(0.5, 0, -5), vector(0, 0, 1), 4.0, 6.0),
+            (point(0, 0.5, 0), vector(0, 0, 1), -1.0, 1.0)
+        ];
+        for (origin, direction, t1, t2) in cases.iter() {
+            let r = Ray::new(*origin, *direction);
+            let xs = c.local_intersect(&r);
+            assert_eq!(xs.len(), 2);
+            assert_eq!(xs[0].t, *t1);
+            assert_eq!(xs[1].t, *t2);
+        }
+    }
+
+    #[test]
+    fn test_ray_misses() {
+        let c = Cube::new();
+        let cases = [
+            (point(-2, 0, 0), vector(0.2673, 0.5345, 0.8018)),
+            (point(0, -2, 0), vector(0.8018, 0.2673, 0.5345)),
+            (point(0, 0, -2), vector(0.5345, 0.8018, 0.2673)),
+            (point(2, 0, 2), vector(0, 0, -1)),
+            (point(0, 2, 2), vector(0, -1, 0)),
+            (point(2, 2, 0), vector(-1, 0, 0))
+        ];
+        for (origin, direction) in cases.iter() {
+            let r = Ray::new(*origin, *direction);
+            assert!(c.local_intersect(&r).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_normal() {
+        let c = Cube::new();
+        let cases = [
+            (point(1, 0.5, -0.8), vector(1, 0, 0)),
+            (point(-1, -0.2, 0.9), vector(-1, 0, 0)),
+            (point(-0.4, 1, -0.1), vector(0, 1, 0)),
+            (point(0.3, -1, -0.7), vector(0, -1, 0)),
+            (point(-0.6, 0.3, 1), vector(0, 0, 1)),
+            (point(0.4, 0.4, -1), vector(0, 0, -1)),
+            (point(1, 1, 1), vector(1, 0, 0)),
+            (point(-1, -1, -1), vector(-1, 0, 0))
+        ];
+        for (p, normal) in cases.iter() {
+            assert_eq!(c.local_normal_at(*p), *normal);
+        }
+    }
 
+    #[test]
+    fn test_transformed_cube() {
+        let mut c = Cube::new();
+        c.set_transform(&transformations::scaling(2, 1, 1));
+        let r = Ray::new(point(-5, 0, 0), vector(1, 0, 0));
+        let xs = c.intersect(&r);
+        assert_eq!(xs[0].t, 3.0);
+        assert_eq!(xs[1].t, 7.0);
+        assert_eq!(c.normal_at(point(-2, 0, 0)), vector(-1, 0, 0));
+    }
 
-pub trait Shape{}
+    #[test]
+    fn test_bounds() {
+        let b = Cube::new().bounds();
+        assert_eq!(b.min, point(-1, -1, -1));
+        assert_eq!(b.max, point(1, 1, 1));
+    }
+}
 
+#[cfg(test)]
+mod tests_cylinder {
+    use super::*;
+    use crate::utils::assert_float_eq;
 
-#[derive(Debug)]
-pub struct Sphere {
-    pub center: PointVector,
-    pub transform: Matrix,
-    pub material: Material,
-    radius: f32 
+    #[test]
+    fn test_new() {
+        let c = Cylinder::new();
+        assert_eq!(c.minimum, f32::NEG_INFINITY);
+        assert_eq!(c.maximum, f32::INFINITY);
+        assert!(!c.closed);
+    }
+
+    #[test]
+    fn test_ray_misses() {
+        let c = Cylinder::new();
+        let cases = [
+            (point(1, 0, 0), vector(0, 1, 0)),
+            (point(0, 0, 0), vector(0, 1, 0)),
+            (point(0, 0, -5), vector(1, 1, 1))
+        ];
+        for (origin, direction) in cases.iter() {
+            let r = Ray::new(*origin, direction.normalize());
+            assert!(c.local_intersect(&r).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_ray_hits() {
+        let c = Cylinder::new();
+        let cases = [
+            (point(1, 0, -5), vector(0, 0, 1), 5.0, 5.0),
+            (point(0, 0, -5), vector(0, 0, 1), 4.0, 6.0),
+            (point(0.5, 0, -5), vector(0.1, 1, 1), 6.80798, 7.08872)
+        ];
+        for (origin, direction, t0, t1) in cases.iter() {
+            let r = Ray::new(*origin, direction.normalize());
+            let xs = c.local_intersect(&r);
+            assert_eq!(xs.len(), 2);
+            assert_float_eq(xs[0].t, *t0);
+            assert_float_eq(xs[1].t, *t1);
+        }
+    }
+
+    #[test]
+    fn test_normal() {
+        let c = Cylinder::new();
+        assert_eq!(c.local_normal_at(point(1, 0, 0)), vector(1, 0, 0));
+        assert_eq!(c.local_normal_at(point(0, 5, -1)), vector(0, 0, -1));
+        assert_eq!(c.local_normal_at(point(0, -2, 1)), vector(0, 0, 1));
+        assert_eq!(c.local_normal_at(point(-1, 1, 0)), vector(-1, 0, 0));
+    }
+
+    #[test]
+    fn test_truncated() {
+        let mut c = Cylinder::new();
+        c.minimum = 1.0;
+        c.maximum = 2.0;
+        let cases = [
+            (point(0, 1.5, 0), vector(0.1, 1, 0), 0),
+            (point(0, 3, -5), vector(0, 0, 1), 0),
+            (point(0, 0, -5), vector(0, 0, 1), 0),
+            (point(0, 2, -5), vector(0, 0, 1), 0),
+            (point(0, 1, -5), vector(0, 0, 1), 0),
+            (point(0, 1.5, -2), vector(0, 0, 1), 2)
+        ];
+        for (origin, direction, count) in cases.iter() {
+            let r = Ray::new(*origin, direction.normalize());
+            assert_eq!(c.local_intersect(&r).len(), *count);
+        }
+    }
+
+    #[test]
+    fn test_closed_caps() {
+        let mut c = Cylinder::new();
+        c.minimum = 1.0;
+        c.maximum = 2.0;
+        c.closed = true;
+        let cases = [
+            (point(0, 3, 0), vector(0, -1, 0), 2),
+            (point(0, 3, -2), vector(0, -1, 2), 2),
+            (point(0, 4, -2), vector(0, -1, 1), 2),
+            (point(0, 0, -2), vector(0, 1, 2), 2),
+            (point(0, -1, -2), vector(0, 1, 1), 2)
+        ];
+        for (origin, direction, count) in cases.iter() {
+            let r = Ray::new(*origin, direction.normalize());
+            assert_eq!(c.local_intersect(&r).len(), *count);
+        }
+    }
+
+    #[test]
+    fn test_normal_on_caps() {
+        let mut c = Cylinder::new();
+        c.minimum = 1.0;
+        c.maximum = 2.0;
+        c.closed = true;
+        assert_eq!(c.local_normal_at(point(0, 1, 0)), vector(0, -1, 0));
+        assert_eq!(c.local_normal_at(point(0.5, 1, 0)), vector(0, -1, 0));
+        assert_eq!(c.local_normal_at(point(0, 1, 0.5)), vector(0, -1, 0));
+        assert_eq!(c.local_normal_at(point(0, 2, 0)), vector(0, 1, 0));
+        assert_eq!(c.local_normal_at(point(0.5, 2, 0)), vector(0, 1, 0));
+        assert_eq!(c.local_normal_at(point(0, 2, 0.5)), vector(0, 1, 0));
+    }
+
+    #[test]
+    fn test_bounds() {
+        let b = Cylinder::new().bounds();
+        assert_eq!(b.min.y, f32::NEG_INFINITY);
+        assert_eq!(b.max.y, f32::INFINITY);
+        let mut c = Cylinder::new();
+        c.minimum = -5.0;
+        c.maximum = 3.0;
+        let b = c.bounds();
+        assert_eq!(b.min, point(-1, -5, -1));
+        assert_eq!(b.max, point(1, 3, 1));
+    }
 }
 
-impl Sphere {
 
-    pub fn new() -> Sphere {
-        Sphere{
-            center: point(0,0,0),
-            transform: Matrix::identity(4),
-            material: Material::default(),
-            radius: 1.0
+#[cfg(test)]
+mod tests_cone {
+    use super::*;
+    use crate::utils::assert_float_eq;
+
+    #[test]
+    fn test_ray_hits() {
+        let c = Cone::new();
+        let cases = [
+            (point(0, 0, -5), vector(0, 0, 1), 5.0, 5.0),
+            (point(0, 0, -5), vector(1, 1, 1), 8.66025, 8.66025),
+            (point(1, 1, -5), vector(-0.5, -1, 1), 4.55006, 49.44994)
+        ];
+        for (origin, direction, t0, t1) in cases.iter() {
+            let r = Ray::new(*origin, direction.normalize());
+            let xs = c.local_intersect(&r);
+            assert_eq!(xs.len(), 2);
+            assert!((xs[0].t - *t0).abs() < 0.001);
+            assert!((xs[1].t - *t1).abs() < 0.001);
         }
     }
 
-    pub fn set_transform(&mut self, m: &Matrix) {
-        self.transform = m.clone()
+    #[test]
+    fn test_ray_parallel_to_half() {
+        let c = Cone::new();
+        let r = Ray::new(point(0, 0, -1), vector(0, 1, 1).normalize());
+        let xs = c.local_intersect(&r);
+        assert_eq!(xs.len(), 1);
+        assert_float_eq(xs[0].t, 0.35355);
+    }
+
+    #[test]
+    fn test_closed_caps() {
+        let mut c = Cone::new();
+        c.minimum = -0.5;
+        c.maximum = 0.5;
+        c.closed = true;
+        let cases = [
+            (point(0, 0, -5), vector(0, 1, 0), 0),
+            (point(0, 0, -0.25), vector(0, 1, 1), 2),
+            (point(0, 0, -0.25), vector(0, 1, 0), 4)
+        ];
+        for (origin, direction, count) in cases.iter() {
+            let r = Ray::new(*origin, direction.normalize());
+            assert_eq!(c.local_intersect(&r).len(), *count);
+        }
     }
 
-    pub fn normal_at(&self, world_point: PointVector) -> PointVector {
-        let object_point = &self.transform.inverse() * &world_point;
-        let mut normal_vector = (object_point - point(0, 0, 0)).normalize();
-        normal_vector = &self.transform.inverse().transpose() * &normal_vector;
-        normal_vector.w = 0.0;
-        normal_vector.normalize()
+    #[test]
+    fn test_normal() {
+        let c = Cone::new();
+        assert_eq!(c.local_normal_at(point(0, 0, 0)), vector(0, 0, 0));
+        assert_eq!(c.local_normal_at(point(1, 1, 1)), vector(1, -(2.0_f32.sqrt()), 1));
+        assert_eq!(c.local_normal_at(point(-1, -1, 0)), vector(-1, 1, 0));
     }
 
+    #[test]
+    fn test_bounds() {
+        assert!(!Cone::new().bounds().is_finite());
+        let mut c = Cone::new();
+        c.minimum = -5.0;
+        c.maximum = 3.0;
+        let b = c.bounds();
+        assert_eq!(b.min, point(-5, -5, -5));
+        assert_eq!(b.max, point(5, 3, 5));
+    }
 }
 
-impl PartialEq for Sphere {
-    fn eq(&self, other: &Self) -> bool {
-        compare_float(&self.radius, &other.radius) && (self.center == other.center)
-        
+#[cfg(test)]
+mod tests_triangle {
+    use super::*;
+    use crate::utils::assert_float_eq;
+
+    fn default_triangle() -> Triangle {
+        Triangle::new(point(0, 1, 0), point(-1, 0, 0), point(1, 0, 0))
+    }
+
+    #[test]
+    fn test_new() {
+        let t = default_triangle();
+        assert_eq!(t.p1, point(0, 1, 0));
+        assert_eq!(t.p2, point(-1, 0, 0));
+        assert_eq!(t.p3, point(1, 0, 0));
+        assert_eq!(t.e1, vector(-1, -1, 0));
+        assert_eq!(t.e2, vector(1, -1, 0));
+        assert_eq!(t.normal, vector(0, 0, -1));
+    }
+
+    #[test]
+    fn test_normal() {
+        let t = default_triangle();
+        assert_eq!(t.local_normal_at(point(0, 0.5, 0)), t.normal);
+        assert_eq!(t.local_normal_at(point(-0.5, 0.75, 0)), t.normal);
+        assert_eq!(t.local_normal_at(point(0.5, 0.25, 0)), t.normal);
+    }
+
+    #[test]
+    fn test_intersect_parallel() {
+        let t = default_triangle();
+        let r = Ray::new(point(0, -1, -2), vector(0, 1, 0));
+        assert_eq!(t.local_intersect(&r).len(), 0);
+    }
+
+    #[test]
+    fn test_intersect_misses_p1_p3_edge() {
+        let t = default_triangle();
+        let r = Ray::new(point(1, 1, -2), vector(0, 0, 1));
+        assert_eq!(t.local_intersect(&r).len(), 0);
+    }
+
+    #[test]
+    fn test_intersect_misses_p1_p2_edge() {
+        let t = default_triangle();
+        let r = Ray::new(point(-1, 1, -2), vector(0, 0, 1));
+        assert_eq!(t.local_intersect(&r).len(), 0);
+    }
+
+    #[test]
+    fn test_intersect_misses_p2_p3_edge() {
+        let t = default_triangle();
+        let r = Ray::new(point(0, -1, -2), vector(0, 0, 1));
+        assert_eq!(t.local_intersect(&r).len(), 0);
+    }
+
+    #[test]
+    fn test_intersect_hit() {
+        let t = default_triangle();
+        let r = Ray::new(point(0, 0.5, -2), vector(0, 0, 1));
+        let xs = t.local_intersect(&r);
+        assert_eq!(xs.len(), 1);
+        assert_eq!(xs[0].t, 2.0);
+    }
+
+    #[test]
+    fn test_intersection_stores_uv() {
+        let t = default_triangle();
+        let r = Ray::new(point(-0.2, 0.3, -2), vector(0, 0, 1));
+        let xs = t.local_intersect(&r);
+        assert_float_eq(xs[0].u, 0.45);
+        assert_float_eq(xs[0].v, 0.25);
+    }
+
+    #[test]
+    fn test_bounds() {
+        let t = Triangle::new(point(-3, 7, 2), point(6, 2, -4), point(2, -1, -1));
+        let b = t.bounds();
+        assert_eq!(b.min, point(-3, -1, -4));
+        assert_eq!(b.max, point(6, 7, 2));
     }
 }
-impl Eq for Sphere {}
 
-impl Shape for Sphere {}
+#[cfg(test)]
+mod tests_smooth_triangle {
+    use super::*;
+    use crate::interactions::{Intersections,prepare_computations};
+
+    fn default_smooth_triangle() -> SmoothTriangle {
+        SmoothTriangle::new(point(0, 1, 0), point(-1, 0, 0), point(1, 0, 0), vector(0, 1, 0), vector(-1, 0, 0), vector(1, 0, 0))
+    }
+
+    #[test]
+    fn test_new() {
+        let t = default_smooth_triangle();
+        assert_eq!(t.p1, point(0, 1, 0));
+        assert_eq!(t.p2, point(-1, 0, 0));
+        assert_eq!(t.p3, point(1, 0, 0));
+        assert_eq!(t.n1, vector(0, 1, 0));
+        assert_eq!(t.n2, vector(-1, 0, 0));
+        assert_eq!(t.n3, vector(1, 0, 0));
+    }
+
+    #[test]
+    fn test_intersection_uv_drives_normal() {
+        let t = default_smooth_triangle();
+        let r = Ray::new(point(-0.2, 0.3, -2), vector(0, 0, 1));
+        let xs = t.local_intersect(&r);
+        // The point is ignored, only the stored u/v pick the normal
+        let n = t.normal_at_hit(point(0, 0, 0), &xs[0]);
+        assert_eq!(n, vector(-0.5547, 0.83205, 0));
+    }
+
+    #[test]
+    fn test_normal_interpolated_with_uv() {
+        let t = default_smooth_triangle();
+        let i = Intersection::new_with_uv(1, &t, 0.45, 0.25);
+        let n = t.normal_at_hit(point(0, 0, 0), &i);
+        assert_eq!(n, vector(-0.5547, 0.83205, 0));
+    }
+
+    #[test]
+    fn test_normal_interpolated_from_point() {
+        let t = default_smooth_triangle();
+        let n = t.normal_at(point(-0.2, 0.3, 0));
+        assert_eq!(n, vector(-0.5547, 0.83205, 0));
+    }
 
+    #[test]
+    fn test_prepare_computations_uses_hit_normal() {
+        let t = default_smooth_triangle();
+        let i = Intersection::new_with_uv(1, &t, 0.45, 0.25);
+        let r = Ray::new(point(-0.2, 0.3, -2), vector(0, 0, 1));
+        let comps = prepare_computations(&i, &r, &Intersections::new(vec![i]));
+        assert_eq!(comps.normalv, vector(-0.5547, 0.83205, 0));
+    }
+}
 
 #[cfg(test)]
-mod tests_sphere {
+mod tests_group {
     use super::*;
     use crate::transformations;
+    use std::f32::consts::PI;
+    use std::sync::atomic::{AtomicBool,Ordering};
 
     #[test]
-    fn test_transformation() {
-        let s = Sphere::new();
-        assert_eq!(s.transform, Matrix::identity(4));
+    fn test_new() {
+        let g = Group::new();
+        assert_eq!(*g.transform(), Matrix::identity(4));
+        assert!(g.children().is_empty());
+    }
 
+    #[test]
+    fn test_add_child() {
+        let mut g = Group::new();
+        g.add_child(Box::new(Sphere::new()));
+        assert_eq!(g.children().len(), 1);
+        assert_eq!(*g.children()[0].parent_transform(), Matrix::identity(4));
     }
 
     #[test]
-    fn test_transformation2() {
-        let mut s = Sphere::new();
-        let t = transformations::translate(2, 3, 4);
-        s.set_transform(&t);
-        assert_eq!(s.transform, t)
+    fn test_intersect_empty() {
+        let g = Group::new();
+        let r = Ray::new(point(0, 0, 0), vector(0, 0, 1));
+        assert!(g.local_intersect(&r).is_empty());
+    }
 
+    #[test]
+    fn test_intersect_nonempty() {
+        let mut g = Group::new();
+        let s1 = Sphere::new();
+        let mut s2 = Sphere::new();
+        s2.set_transform(&transformations::translate(0, 0, -3));
+        let mut s3 = Sphere::new();
+        s3.set_transform(&transformations::translate(5, 0, 0));
+        g.add_child(Box::new(s1));
+        g.add_child(Box::new(s2));
+        g.add_child(Box::new(s3));
+        let r = Ray::new(point(0, 0, -5), vector(0, 0, 1));
+        let xs = g.local_intersect(&r);
+        assert_eq!(xs.len(), 4);
+        let s1 = g.children()[0].as_ref();
+        let s2 = g.children()[1].as_ref();
+        assert!(std::ptr::addr_eq(xs[0].obj, s2));
+        assert!(std::ptr::addr_eq(xs[1].obj, s2));
+        assert!(std::ptr::addr_eq(xs[2].obj, s1));
+        assert!(std::ptr::addr_eq(xs[3].obj, s1));
     }
+
     #[test]
-    fn test_normal_at() {
-        let s = Sphere::new();
-        let n = s.normal_at(point(1,0,0));
-        assert_eq!(n, vector(1, 0, 0));
+    fn test_intersect_transformed() {
+        let mut g = Group::new();
+        g.set_transform(&transformations::scaling(2, 2, 2));
+        let mut s = Sphere::new();
+        s.set_transform(&transformations::translate(5, 0, 0));
+        g.add_child(Box::new(s));
+        let r = Ray::new(point(10, 0, -10), vector(0, 0, 1));
+        assert_eq!(g.intersect(&r).len(), 2);
+    }
+
+    fn nested_sphere(inner_scaling: Matrix, group_transform_after: bool) -> Group {
+        let mut g1 = Group::new();
+        if !group_transform_after {
+            g1.set_transform(&transformations::rotation_y(PI / 2.0));
+        }
+        let mut g2 = Group::new();
+        g2.set_transform(&inner_scaling);
+        let mut s = Sphere::new();
+        s.set_transform(&transformations::translate(5, 0, 0));
+        g2.add_child(Box::new(s));
+        g1.add_child(Box::new(g2));
+        if group_transform_after {
+            g1.set_transform(&transformations::rotation_y(PI / 2.0));
+        }
+        g1
+    }
+
+    fn first_leaf(g: &Group) -> &dyn Shape {
+        let inner = (g.children()[0].as_ref() as &dyn Any).downcast_ref::<Group>().unwrap();
+        inner.children()[0].as_ref()
     }
+
     #[test]
-    fn test_normal_at2() {
-        let s = Sphere::new();
-        let n = s.normal_at(point(0,1,0));
-        assert_eq!(n, vector(0, 1, 0));
+    fn test_world_to_object() {
+        let g = nested_sphere(transformations::scaling(2, 2, 2), false);
+        let s = first_leaf(&g);
+        assert_eq!(s.world_to_object(point(-2, 0, -10)), point(0, 0, -1));
     }
+
     #[test]
-    fn test_normal_at3() {
-        let s = Sphere::new();
-        let n = s.normal_at(point(0,0,1));
-        assert_eq!(n, vector(0, 0, 1));
+    fn test_normal_to_world() {
+        let g = nested_sphere(transformations::scaling(1, 2, 3), false);
+        let s = first_leaf(&g);
+        let c = 3.0_f32.sqrt() / 3.0;
+        assert_eq!(s.normal_to_world(vector(c, c, c)), vector(0.2857, 0.4286, -0.8571));
     }
+
     #[test]
-    fn test_normal_at4() {
-        let s = Sphere::new();
-        let c = 3.0_f32.sqrt()/3.0;
-        let n = s.normal_at(point(c,c,c));
-        assert_eq!(n, vector(c, c, c));
+    fn test_normal_on_child() {
+        let g = nested_sphere(transformations::scaling(1, 2, 3), false);
+        let s = first_leaf(&g);
+        assert_eq!(s.normal_at(point(1.7321, 1.1547, -5.5774)), vector(0.2857, 0.42854, -0.85716));
     }
 
     #[test]
-    fn test_normal_at5() {
-        let s = Sphere::new();
-        let c = 3.0_f32.sqrt()/3.0;
-        let n = s.normal_at(point(c,c,c));
-        assert_eq!(n, n.normalize());
+    fn test_transform_set_after_adding_children() {
+        let g = nested_sphere(transformations::scaling(2, 2, 2), true);
+        let s = first_leaf(&g);
+        assert_eq!(s.world_to_object(point(-2, 0, -10)), point(0, 0, -1));
     }
 
     #[test]
-    fn test_normal_at6() {
+    fn test_bounds() {
         let mut s = Sphere::new();
-        s.set_transform(&transformations::translate(0, 1, 0));
-        let n = s.normal_at(point(0,1.70711,-0.70711));
-        assert_eq!(n, vector(0, 0.70711, -0.70711));
+        s.set_transform(&(&transformations::translate(2, 5, -3) * &transformations::scaling(2, 2, 2)));
+        let mut c = Cylinder::new();
+        c.minimum = -2.0;
+        c.maximum = 2.0;
+        c.set_transform(&(&transformations::translate(-4, -1, 4) * &transformations::scaling(0.5, 1, 0.5)));
+        let mut g = Group::new();
+        g.add_child(Box::new(s));
+        g.add_child(Box::new(c));
+        let b = g.bounds();
+        assert_eq!(b.min, point(-4.5, -3, -5));
+        assert_eq!(b.max, point(4, 7, 4.5));
+    }
+
+    // Records whether the group ever asked it for intersections
+    #[derive(Debug)]
+    struct ProbeShape {
+        material: Material,
+        transforms: ShapeTransform,
+        probed: AtomicBool
+    }
+
+    impl Shape for ProbeShape {
+        fn local_intersect(&self, _r: &Ray) -> Vec<Intersection<'_>> {
+            self.probed.store(true, Ordering::SeqCst);
+            vec![]
+        }
+
+        fn local_normal_at(&self, object_point: PointVector) -> PointVector {
+            object_point - point(0, 0, 0)
+        }
+
+        fn bounds(&self) -> BoundingBox {
+            BoundingBox::new(point(-1, -1, -1), point(1, 1, 1))
+        }
+
+        fn transforms(&self) -> &ShapeTransform {
+            &self.transforms
+        }
+
+        fn set_parent_transform(&mut self, m: &Matrix) {
+            self.transforms.set_parent(m)
+        }
+
+        fn material(&self) -> &Material {
+            &self.material
+        }
+    }
+
+    fn probe() -> Box<ProbeShape> {
+        Box::new(ProbeShape {
+            material: Material::default(),
+            transforms: ShapeTransform::new(),
+            probed: AtomicBool::new(false)
+        })
+    }
+
+    fn was_probed(g: &Group) -> bool {
+        let child = (g.children()[0].as_ref() as &dyn Any).downcast_ref::<ProbeShape>().unwrap();
+        child.probed.load(Ordering::SeqCst)
     }
 
     #[test]
-    fn test_normal_at7() {
+    fn test_intersect_skips_children_when_bounds_missed() {
+        let mut g = Group::new();
+        g.add_child(probe());
+        g.intersect(&Ray::new(point(0, 0, -5), vector(0, 1, 0)));
+        assert!(!was_probed(&g));
+    }
+
+    #[test]
+    fn test_intersect_tests_children_when_bounds_hit() {
+        let mut g = Group::new();
+        g.add_child(probe());
+        g.intersect(&Ray::new(point(0, 0, -5), vector(0, 0, 1)));
+        assert!(was_probed(&g));
+    }
+
+    fn sphere_at(x: f32, y: f32, z: f32) -> Box<Sphere> {
         let mut s = Sphere::new();
-        let m = &transformations::scaling(1, 0.5, 1) * &transformations::rotation_z(std::f32::consts::PI/5.0);
-        s.set_transform(&m);
-        let y = 2.0_f32.sqrt()/2.0;
-        let z = -y;
-        let n = s.normal_at(point(0,y,z));
-        assert_eq!(n, vector(0, 0.97014, -0.24254));
+        s.set_transform(&transformations::translate(x, y, z));
+        Box::new(s)
+    }
+
+    fn as_group(shape: &dyn Shape) -> &Group {
+        (shape as &dyn Any).downcast_ref::<Group>().unwrap()
     }
 
     #[test]
-    fn test_sphere_material() {
-        let s = Sphere::new();
-        let m = &s.material;
-        assert_eq!(*m, Material::default());
+    fn test_divide_splits_clusters() {
+        let mut g = Group::new();
+        g.add_child(sphere_at(10.0, 0.0, 0.0));
+        g.add_child(sphere_at(-10.0, 0.0, 0.0));
+        g.add_child(sphere_at(8.0, 0.0, 0.0));
+        g.add_child(sphere_at(-8.0, 0.0, 0.0));
+        g.divide(4);
+        assert_eq!(g.children().len(), 2);
+        let left = as_group(g.children()[0].as_ref());
+        let right = as_group(g.children()[1].as_ref());
+        assert_eq!(left.children().len(), 2);
+        assert_eq!(right.children().len(), 2);
+        assert_eq!(left.bounds().min, point(-11, -1, -1));
+        assert_eq!(left.bounds().max, point(-7, 1, 1));
+        assert_eq!(right.bounds().min, point(7, -1, -1));
+        assert_eq!(right.bounds().max, point(11, 1, 1));
     }
 
     #[test]
-    fn test_sphere_material2() {
-        let mut s = Sphere::new();
-        let mut m = Material::default();
-        m.ambient = 1.0;
-        s.material = m;
-        assert_eq!(s.material,m);
+    fn test_divide_rejects_costlier_split() {
+        // Children that all fill the same box gain nothing from being split
+        let mut g = Group::new();
+        for _ in 0..4 {
+            g.add_child(sphere_at(1.0, 2.0, 3.0));
+        }
+        g.divide(2);
+        assert_eq!(g.children().len(), 4);
+        assert!(g.children().iter().all(|c| (c.as_ref() as &dyn Any).is::<Sphere>()));
+    }
+
+    #[test]
+    fn test_divide_below_threshold() {
+        let mut g = Group::new();
+        g.add_child(sphere_at(-2.0, 0.0, 0.0));
+        g.add_child(sphere_at(2.0, 0.0, 0.0));
+        g.divide(3);
+        assert_eq!(g.children().len(), 2);
+        assert!((g.children()[0].as_ref() as &dyn Any).is::<Sphere>());
+    }
+
+    #[test]
+    fn test_divide_keeps_unbounded_children() {
+        let mut g = Group::new();
+        g.add_child(sphere_at(-2.0, 0.0, 0.0));
+        g.add_child(Box::new(Plane::new()));
+        g.add_child(sphere_at(2.0, 0.0, 0.0));
+        g.divide(2);
+        assert_eq!(g.children().len(), 3);
+        assert!((g.children()[0].as_ref() as &dyn Any).is::<Plane>());
+        assert_eq!(as_group(g.children()[1].as_ref()).children().len(), 1);
+        assert_eq!(as_group(g.children()[2].as_ref()).children().len(), 1);
+    }
+
+    #[test]
+    fn test_divide_recurses_into_subgroups() {
+        let mut g = Group::new();
+        for i in 0..8 {
+            g.add_child(sphere_at(i as f32 * 3.0, 0.0, 0.0));
+        }
+        g.divide(2);
+        let mut depth = 0;
+        let mut node = &g;
+        while node.children().len() == 2 && (node.children()[0].as_ref() as &dyn Any).is::<Group>() {
+            node = as_group(node.children()[0].as_ref());
+            depth += 1;
+        }
+        assert_eq!(depth, 3);
+        assert_eq!(node.children().len(), 1);
+    }
+
+    #[test]
+    fn test_divide_preserves_intersections() {
+        let build = || {
+            let mut g = Group::new();
+            g.set_transform(&transformations::rotation_y(PI / 6.0));
+            for i in 0..5 {
+                for j in 0..5 {
+                    g.add_child(sphere_at(i as f32 * 2.5, j as f32 * 2.5, 0.0));
+                }
+            }
+            g
+        };
+        let flat = build();
+        let mut divided = build();
+        divided.divide(3);
+        for i in 0..20 {
+            let r = Ray::new(point(i as f32 * 0.6 - 1.0, i as f32 * 0.5, -20), vector(0.1, 0.05, 1).normalize());
+            let expected: Vec<f32> = flat.intersect(&r).iter().map(|x| x.t).collect();
+            let actual: Vec<f32> = divided.intersect(&r).iter().map(|x| x.t).collect();
+            assert_eq!(expected, actual);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests_csg {
+    use super::*;
+    use crate::transformations;
+
+    #[test]
+    fn test_new() {
+        let c = Csg::new(CsgOperation::Union, Box::new(Sphere::new()), Box::new(Plane::new()));
+        assert_eq!(c.operation, CsgOperation::Union);
+        assert!((c.left() as &dyn Any).downcast_ref::<Sphere>().is_some());
+        assert!((c.right() as &dyn Any).downcast_ref::<Plane>().is_some());
+        assert!(c.includes(c.left()));
+        assert!(c.includes(c.right()));
+    }
+
+    #[test]
+    fn test_intersection_allowed() {
+        let table = [
+            (CsgOperation::Union, [false, true, false, true, false, false, true, true]),
+            (CsgOperation::Intersection, [true, false, true, false, true, true, false, false]),
+            (CsgOperation::Difference, [false, true, false, true, true, true, false, false])
+        ];
+        for (op, expected) in table.iter() {
+            let mut index = 0;
+            for lhit in [true, false] {
+                for inl in [true, false] {
+                    for inr in [true, false] {
+                        assert_eq!(intersection_allowed(*op, lhit, inl, inr), expected[index]);
+                        index += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_filter_intersections() {
+        let expected = [
+            (CsgOperation::Union, [0, 3]),
+            (CsgOperation::Intersection, [1, 2]),
+            (CsgOperation::Difference, [0, 1])
+        ];
+        for (op, [x0, x1]) in expected.iter() {
+            let c = Csg::new(*op, Box::new(Sphere::new()), Box::new(Cube::new()));
+            let xs = vec![
+                Intersection::new(1, c.left()), Intersection::new(2, c.right()),
+                Intersection::new(3, c.left()), Intersection::new(4, c.right())
+            ];
+            let result = c.filter_intersections(xs.clone());
+            assert_eq!(result.len(), 2);
+            assert_eq!(result[0], xs[*x0]);
+            assert_eq!(result[1], xs[*x1]);
+        }
+    }
+
+    #[test]
+    fn test_ray_misses() {
+        let c = Csg::new(CsgOperation::Union, Box::new(Sphere::new()), Box::new(Sphere::new()));
+        let r = Ray::new(point(0, 2, -5), vector(0, 0, 1));
+        assert!(c.local_intersect(&r).is_empty());
+    }
+
+    #[test]
+    fn test_ray_hits() {
+        let mut s2 = Sphere::new();
+        s2.set_transform(&transformations::translate(0, 0, 0.5));
+        let c = Csg::new(CsgOperation::Union, Box::new(Sphere::new()), Box::new(s2));
+        let r = Ray::new(point(0, 0, -5), vector(0, 0, 1));
+        let xs = c.local_intersect(&r);
+        assert_eq!(xs.len(), 2);
+        assert_eq!(xs[0].t, 4.0);
+        assert!(std::ptr::addr_eq(xs[0].obj, c.left()));
+        assert_eq!(xs[1].t, 6.5);
+        assert!(std::ptr::addr_eq(xs[1].obj, c.right()));
+    }
+
+    #[test]
+    fn test_difference_with_group_child() {
+        let mut g = Group::new();
+        g.add_child(Box::new(Sphere::new()));
+        let mut hole = Sphere::new();
+        hole.set_transform(&transformations::scaling(0.5, 0.5, 0.5));
+        let c = Csg::new(CsgOperation::Difference, Box::new(g), Box::new(hole));
+        let r = Ray::new(point(0, 0, -5), vector(0, 0, 1));
+        let xs = c.intersect(&r);
+        let ts: Vec<f32> = xs.iter().map(|i| i.t).collect();
+        assert_eq!(ts, vec![4.0, 4.5, 5.5, 6.0]);
+    }
+
+    #[test]
+    fn test_transformed_csg() {
+        let mut c = Csg::new(CsgOperation::Intersection, Box::new(Sphere::new()), Box::new(Sphere::new()));
+        c.set_transform(&transformations::translate(0, 0, 1));
+        let r = Ray::new(point(0, 0, -5), vector(0, 0, 1));
+        let xs = c.intersect(&r);
+        assert_eq!(xs[0].t, 5.0);
+        assert_eq!(xs[0].obj.normal_at(point(0, 0, 0)), vector(0, 0, -1));
+    }
+
+    #[test]
+    fn test_bounds() {
+        let left = Sphere::new();
+        let mut right = Sphere::new();
+        right.set_transform(&transformations::translate(2, 3, 4));
+        let c = Csg::new(CsgOperation::Difference, Box::new(left), Box::new(right));
+        let b = c.bounds();
+        assert_eq!(b.min, point(-1, -1, -1));
+        assert_eq!(b.max, point(3, 4, 5));
+    }
+
+    #[test]
+    fn test_divide_children() {
+        let mut left = Group::new();
+        for x in [-4, -3, 3, 4] {
+            let mut s = Sphere::new();
+            s.set_transform(&transformations::translate(x, 0, 0));
+            left.add_child(Box::new(s));
+        }
+        let mut c = Csg::new(CsgOperation::Union, Box::new(left), Box::new(Sphere::new()));
+        c.divide(4);
+        let left = (c.left() as &dyn Any).downcast_ref::<Group>().unwrap();
+        assert_eq!(left.children().len(), 2);
     }
 }
 
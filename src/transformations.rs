@@ -1,6 +1,6 @@
 use crate::matrix::Matrix;
-use crate::point_vector::{point,vector};
-use std::f32::consts;
+use crate::point_vector::PointVector;
+use crate::quaternion::Quaternion;
 
 
 pub fn translate(x: impl Into<f64>, y: impl Into<f64>, z: impl Into<f64>) -> Matrix {
@@ -61,6 +61,11 @@ pub fn rotation_z(r: impl Into<f64>) -> Matrix {
     m
 }
 
+// Rotation by r radians about any axis through the origin
+pub fn rotation(axis: PointVector, r: impl Into<f64>) -> Matrix {
+    Quaternion::from_axis_angle(axis, r).to_matrix()
+}
+
 pub fn shearing(x_y: impl Into<f64>, x_z: impl Into<f64>, y_x: impl Into<f64>, y_z: impl Into<f64>, z_x: impl Into<f64>, z_y: impl Into<f64>) -> Matrix {
     let mut m = Matrix::identity(4);
     let x_y = x_y.into() as f32;
@@ -78,10 +83,93 @@ pub fn shearing(x_y: impl Into<f64>, x_z: impl Into<f64>, y_x: impl Into<f64>, y
     m
 }
 
+pub fn view_transform(from: PointVector, to: PointVector, up: PointVector) -> Matrix {
+    let forward = (to - from).normalize();
+    let left = forward * up.normalize();
+    let true_up = left * forward;
+    let orientation = Matrix::new(vec![
+        vec![left.x, left.y, left.z, 0.0],
+        vec![true_up.x, true_up.y, true_up.z, 0.0],
+        vec![-forward.x, -forward.y, -forward.z, 0.0],
+        vec![0.0, 0.0, 0.0, 1.0]
+    ]);
+    &orientation * &translate(-from.x, -from.y, -from.z)
+}
+
+
+// Chains transforms in the order they are applied, so
+// Transform::identity().rotate_x(a).scale(5, 5, 5) rotates first and then scales,
+// the same as &scaling(5, 5, 5) * &rotation_x(a)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transform {
+    matrix: Matrix
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl Transform {
+    pub fn identity() -> Transform {
+        Transform {
+            matrix: Matrix::identity(4)
+        }
+    }
+
+    // Applies m after everything chained so far
+    pub fn then(self, m: &Matrix) -> Transform {
+        Transform {
+            matrix: m * &self.matrix
+        }
+    }
+
+    pub fn translate(self, x: impl Into<f64>, y: impl Into<f64>, z: impl Into<f64>) -> Transform {
+        self.then(&translate(x, y, z))
+    }
+
+    pub fn scale(self, x: impl Into<f64>, y: impl Into<f64>, z: impl Into<f64>) -> Transform {
+        self.then(&scaling(x, y, z))
+    }
+
+    pub fn rotate_x(self, r: impl Into<f64>) -> Transform {
+        self.then(&rotation_x(r))
+    }
+
+    pub fn rotate_y(self, r: impl Into<f64>) -> Transform {
+        self.then(&rotation_y(r))
+    }
+
+    pub fn rotate_z(self, r: impl Into<f64>) -> Transform {
+        self.then(&rotation_z(r))
+    }
+
+    pub fn rotate(self, axis: PointVector, r: impl Into<f64>) -> Transform {
+        self.then(&rotation(axis, r))
+    }
+
+    pub fn shear(self, x_y: impl Into<f64>, x_z: impl Into<f64>, y_x: impl Into<f64>, y_z: impl Into<f64>, z_x: impl Into<f64>, z_y: impl Into<f64>) -> Transform {
+        self.then(&shearing(x_y, x_z, y_x, y_z, z_x, z_y))
+    }
+
+    pub fn matrix(&self) -> &Matrix {
+        &self.matrix
+    }
+}
+
+impl From<Transform> for Matrix {
+    fn from(t: Transform) -> Matrix {
+        t.matrix
+    }
+}
+
 
 #[cfg(test)]
 mod tests_matrix {
     use super::*;
+    use crate::point_vector::{point,vector};
+    use std::f32::consts;
 
     #[test]
     fn test_transform1() {
@@ -212,6 +300,7 @@ mod tests_matrix {
     }
 
     #[test]
+    #[allow(non_snake_case)]
     fn test_all_transformations1() {
         let p = point(1,0,1);
         let A = rotation_x(consts::PI/2.0);
@@ -232,6 +321,7 @@ mod tests_matrix {
     }
 
     #[test]
+    #[allow(non_snake_case)]
     fn test_all_transformations2() {
         let p = point(1,0,1);
         let A = rotation_x(consts::PI/2.0);
@@ -244,4 +334,80 @@ mod tests_matrix {
 
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_view_transform_default() {
+        let t = view_transform(point(0, 0, 0), point(0, 0, -1), vector(0, 1, 0));
+        assert_eq!(t, Matrix::identity(4));
+    }
+
+    #[test]
+    fn test_view_transform_positive_z() {
+        let t = view_transform(point(0, 0, 0), point(0, 0, 1), vector(0, 1, 0));
+        assert_eq!(t, scaling(-1, 1, -1));
+    }
+
+    #[test]
+    fn test_view_transform_moves_world() {
+        let t = view_transform(point(0, 0, 8), point(0, 0, 0), vector(0, 1, 0));
+        assert_eq!(t, translate(0, 0, -8));
+    }
+
+    #[test]
+    fn test_view_transform_arbitrary() {
+        let t = view_transform(point(1, 3, 2), point(4, -2, 8), vector(1, 1, 0));
+        assert_eq!(t, Matrix::new(vec![
+            vec![-0.50709, 0.50709, 0.67612, -2.36643],
+            vec![0.76772, 0.60609, 0.12122, -2.82843],
+            vec![-0.35857, 0.59761, -0.71714, 0.0],
+            vec![0.0, 0.0, 0.0, 1.0]
+        ]));
+    }
+}
+
+
+#[cfg(test)]
+mod tests_transform {
+    use super::*;
+    use crate::point_vector::{point,vector};
+    use std::f32::consts::PI;
+
+    #[test]
+    fn test_identity() {
+        assert_eq!(*Transform::identity().matrix(), Matrix::identity(4));
+        assert_eq!(Transform::default(), Transform::identity());
+    }
+
+    #[test]
+    fn test_chained_in_reading_order() {
+        let p = point(1, 0, 1);
+        let t = Transform::identity().rotate_x(PI / 2.0).scale(5, 5, 5).translate(10, 5, 7);
+        assert_eq!(t.matrix() * &p, point(15, 0, 7));
+    }
+
+    #[test]
+    fn test_matches_free_functions() {
+        let t = Transform::identity().rotate_x(PI / 2.0).scale(5, 5, 5).translate(10, 5, 7);
+        let expected = &(&translate(10, 5, 7) * &scaling(5, 5, 5)) * &rotation_x(PI / 2.0);
+        assert_eq!(Matrix::from(t), expected);
+
+        let t = Transform::identity().rotate_z(PI / 5.0).scale(1, 0.5, 1);
+        assert_eq!(*t.matrix(), &scaling(1, 0.5, 1) * &rotation_z(PI / 5.0));
+
+        let t = Transform::identity().shear(1, 0, 0, 0, 0, 1).rotate_y(0.3);
+        assert_eq!(*t.matrix(), &rotation_y(0.3) * &shearing(1, 0, 0, 0, 0, 1));
+    }
+
+    #[test]
+    fn test_then() {
+        let view = view_transform(point(1, 3, 2), point(4, -2, 8), vector(1, 1, 0));
+        let t = Transform::identity().translate(1, 2, 3).then(&view);
+        assert_eq!(*t.matrix(), &view * &translate(1, 2, 3));
+    }
+
+    #[test]
+    fn test_rotate_about_axis() {
+        let t = Transform::identity().rotate(vector(0, 0, 1), PI / 2.0).translate(0, 0, 1);
+        assert_eq!(*t.matrix(), &translate(0, 0, 1) * &rotation_z(PI / 2.0));
+        assert_eq!(&rotation(vector(0, 1, 0), PI / 2.0) * &point(0, 0, 1), point(1, 0, 0));
+    }
+}
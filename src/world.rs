@@ -0,0 +1,444 @@
+use crate::shapes::Shape;
+use crate::point_light::PointLight;
+use crate::ray::Ray;
+use crate::point_vector::PointVector;
+use crate::color::Color;
+use crate::interactions::{intersect,lighting,prepare_computations,schlick,Intersections,Computations};
+
+
+pub struct World {
+    pub objects: Vec<Box<dyn Shape>>,
+    pub lights: Vec<PointLight>,
+    // How many times a ray may bounce before it stops contributing color
+    pub max_depth: usize
+}
+
+impl Default for World {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl World {
+    pub fn new() -> World {
+        World {
+            objects: vec![],
+            lights: vec![],
+            max_depth: 5
+        }
+    }
+
+    pub fn intersect_world(&self, r: &Ray) -> Intersections<'_> {
+        let mut v = vec![];
+        for obj in self.objects.iter() {
+            v.extend(intersect(obj.as_ref(), r));
+        }
+        let mut xs = Intersections::new(v);
+        xs.sort();
+        xs
+    }
+
+    pub fn shade_hit(&self, comps: &Computations, remaining: usize) -> Color {
+        let mut c = Color::new(0, 0, 0);
+        for light in self.lights.iter() {
+            let shadowed = self.is_shadowed(light, comps.over_point);
+            c = c + lighting(comps.obj.material(), comps.obj, *light, comps.over_point, comps.eyev, comps.normalv, shadowed);
+        }
+        let reflected = self.reflected_color(comps, remaining);
+        let refracted = self.refracted_color(comps, remaining);
+        let material = comps.obj.material();
+        if material.reflective > 0.0 && material.transparency > 0.0 {
+            let reflectance = schlick(comps);
+            c + reflected * reflectance + refracted * (1.0 - reflectance)
+        } else {
+            c + reflected + refracted
+        }
+    }
+
+    pub fn reflected_color(&self, comps: &Computations, remaining: usize) -> Color {
+        let reflective = comps.obj.material().reflective;
+        if remaining == 0 || reflective == 0.0 {
+            return Color::new(0, 0, 0)
+        }
+        let reflect_ray = Ray::new(comps.over_point, comps.reflectv);
+        self.color_at_depth(&reflect_ray, remaining - 1) * reflective
+    }
+
+    pub fn refracted_color(&self, comps: &Computations, remaining: usize) -> Color {
+        let transparency = comps.obj.material().transparency;
+        if remaining == 0 || transparency == 0.0 {
+            return Color::new(0, 0, 0)
+        }
+        // Snell's law, bailing out on total internal reflection
+        let n_ratio = comps.n1 / comps.n2;
+        let cos_i = comps.eyev.dot(&comps.normalv);
+        let sin2_t = n_ratio.powi(2) * (1.0 - cos_i.powi(2));
+        if sin2_t > 1.0 {
+            return Color::new(0, 0, 0)
+        }
+        let cos_t = (1.0 - sin2_t).sqrt();
+        let direction = comps.normalv * (n_ratio * cos_i - cos_t) - comps.eyev * n_ratio;
+        let refract_ray = Ray::new(comps.under_point, direction);
+        self.color_at_depth(&refract_ray, remaining - 1) * transparency
+    }
+
+    pub fn is_shadowed(&self, light: &PointLight, point: PointVector) -> bool {
+        let v = light.position - point;
+        let distance = v.magnitude();
+        let r = Ray::new(point, v.normalize());
+        match self.intersect_world(&r).hit() {
+            Some(hit) => hit.t < distance,
+            None => false
+        }
+    }
+
+    pub fn color_at(&self, r: &Ray) -> Color {
+        self.color_at_depth(r, self.max_depth)
+    }
+
+    pub fn color_at_depth(&self, r: &Ray, remaining: usize) -> Color {
+        let xs = self.intersect_world(r);
+        match xs.hit() {
+            Some(hit) => {
+                let comps = prepare_computations(&hit, r, &xs);
+                self.shade_hit(&comps, remaining)
+            },
+            None => Color::new(0, 0, 0)
+        }
+    }
+}
+
+
+#[cfg(test)]
+pub(crate) fn default_world() -> World {
+    use crate::point_vector::point;
+    use crate::shapes::Sphere;
+    use crate::transformations;
+
+    let mut s1 = Sphere::new();
+    s1.material.color = Color::new(0.8, 1.0, 0.6);
+    s1.material.diffuse = 0.7;
+    s1.material.specular = 0.2;
+    let mut s2 = Sphere::new();
+    s2.set_transform(&transformations::scaling(0.5, 0.5, 0.5));
+    let mut w = World::new();
+    w.objects = vec![Box::new(s1), Box::new(s2)];
+    w.lights = vec![PointLight::new(point(-10, 10, -10), Color::new(1, 1, 1))];
+    w
+}
+
+
+#[cfg(test)]
+mod tests_world {
+    use super::*;
+    use crate::point_vector::{point,vector};
+    use crate::interactions::Intersection;
+    use crate::shapes::{Sphere,Plane};
+    use crate::patterns::TestPattern;
+    use crate::transformations;
+    use std::sync::Arc;
+
+    fn reflective_plane() -> Plane {
+        let mut p = Plane::new();
+        p.material.reflective = 0.5;
+        p.set_transform(&transformations::translate(0, -1, 0));
+        p
+    }
+
+    #[test]
+    fn test_new() {
+        let w = World::new();
+        assert_eq!(w.objects.len(), 0);
+        assert_eq!(w.lights.len(), 0);
+    }
+
+    #[test]
+    fn test_intersect_world() {
+        let w = default_world();
+        let r = Ray::new(point(0, 0, -5), vector(0, 0, 1));
+        let xs = w.intersect_world(&r);
+        assert_eq!(xs.v.len(), 4);
+        assert_eq!(xs.v[0].t, 4.0);
+        assert_eq!(xs.v[1].t, 4.5);
+        assert_eq!(xs.v[2].t, 5.5);
+        assert_eq!(xs.v[3].t, 6.0);
+    }
+
+    #[test]
+    fn test_shade_hit() {
+        let w = default_world();
+        let r = Ray::new(point(0, 0, -5), vector(0, 0, 1));
+        let i = Intersection::new(4, w.objects[0].as_ref());
+        let comps = prepare_computations(&i, &r, &Intersections::new(vec![i]));
+        assert_eq!(w.shade_hit(&comps, w.max_depth), Color::new(0.38066, 0.47583, 0.2855));
+    }
+
+    #[test]
+    fn test_shade_hit_inside() {
+        let mut w = default_world();
+        w.lights = vec![PointLight::new(point(0, 0.25, 0), Color::new(1, 1, 1))];
+        let r = Ray::new(point(0, 0, 0), vector(0, 0, 1));
+        let i = Intersection::new(0.5, w.objects[1].as_ref());
+        let comps = prepare_computations(&i, &r, &Intersections::new(vec![i]));
+        assert_eq!(w.shade_hit(&comps, w.max_depth), Color::new(0.90498, 0.90498, 0.90498));
+    }
+
+    #[test]
+    fn test_shade_hit_many_lights() {
+        let mut w = default_world();
+        let r = Ray::new(point(0, 0, -5), vector(0, 0, 1));
+        let single = w.color_at(&r);
+        w.lights.push(PointLight::new(point(-10, 10, -10), Color::new(1, 1, 1)));
+        assert_eq!(w.color_at(&r), single * 2);
+    }
+
+    #[test]
+    fn test_color_at_miss() {
+        let w = default_world();
+        let r = Ray::new(point(0, 0, -5), vector(0, 1, 0));
+        assert_eq!(w.color_at(&r), Color::new(0, 0, 0));
+    }
+
+    #[test]
+    fn test_color_at_hit() {
+        let w = default_world();
+        let r = Ray::new(point(0, 0, -5), vector(0, 0, 1));
+        assert_eq!(w.color_at(&r), Color::new(0.38066, 0.47583, 0.2855));
+    }
+
+    #[test]
+    fn test_color_at_behind_ray() {
+        let mut outer = Sphere::new();
+        outer.material.color = Color::new(0.8, 1.0, 0.6);
+        outer.material.ambient = 1.0;
+        let mut inner = Sphere::new();
+        inner.set_transform(&transformations::scaling(0.5, 0.5, 0.5));
+        inner.material.ambient = 1.0;
+        let inner_color = inner.material.color;
+        let mut w = default_world();
+        w.objects = vec![Box::new(outer), Box::new(inner)];
+        let r = Ray::new(point(0, 0, 0.75), vector(0, 0, -1));
+        assert_eq!(w.color_at(&r), inner_color);
+    }
+
+    #[test]
+    fn test_is_shadowed_nothing_collinear() {
+        let w = default_world();
+        assert!(!w.is_shadowed(&w.lights[0], point(0, 10, 0)));
+    }
+
+    #[test]
+    fn test_is_shadowed_object_between() {
+        let w = default_world();
+        assert!(w.is_shadowed(&w.lights[0], point(10, -10, 10)));
+    }
+
+    #[test]
+    fn test_is_shadowed_object_behind_light() {
+        let w = default_world();
+        assert!(!w.is_shadowed(&w.lights[0], point(-20, 20, -20)));
+    }
+
+    #[test]
+    fn test_is_shadowed_object_behind_point() {
+        let w = default_world();
+        assert!(!w.is_shadowed(&w.lights[0], point(-2, 2, -2)));
+    }
+
+    #[test]
+    fn test_shade_hit_in_shadow() {
+        let mut w = World::new();
+        w.lights.push(PointLight::new(point(0, 0, -10), Color::new(1, 1, 1)));
+        let mut s2 = Sphere::new();
+        s2.set_transform(&transformations::translate(0, 0, 10));
+        w.objects = vec![Box::new(Sphere::new()), Box::new(s2)];
+        let r = Ray::new(point(0, 0, 5), vector(0, 0, 1));
+        let i = Intersection::new(4, w.objects[1].as_ref());
+        let comps = prepare_computations(&i, &r, &Intersections::new(vec![i]));
+        assert_eq!(w.shade_hit(&comps, w.max_depth), Color::new(0.1, 0.1, 0.1));
+    }
+
+    #[test]
+    fn test_shade_hit_shadowed_by_one_light() {
+        let mut w = default_world();
+        let r = Ray::new(point(0, 0, -5), vector(0, 0, 1));
+        let lit = w.color_at(&r);
+        // A second light hidden inside the outer sphere only adds ambient
+        w.lights.push(PointLight::new(point(0, 0, 0), Color::new(1, 1, 1)));
+        assert_eq!(w.color_at(&r), lit + Color::new(0.08, 0.1, 0.06));
+    }
+
+    #[test]
+    fn test_reflected_color_nonreflective() {
+        let mut inner = Sphere::new();
+        inner.set_transform(&transformations::scaling(0.5, 0.5, 0.5));
+        inner.material.ambient = 1.0;
+        let mut w = default_world();
+        w.objects[1] = Box::new(inner);
+        let r = Ray::new(point(0, 0, 0), vector(0, 0, 1));
+        let i = Intersection::new(1, w.objects[1].as_ref());
+        let comps = prepare_computations(&i, &r, &Intersections::new(vec![i]));
+        assert_eq!(w.reflected_color(&comps, w.max_depth), Color::new(0, 0, 0));
+    }
+
+    #[test]
+    fn test_reflected_color_reflective() {
+        let mut w = default_world();
+        w.objects.push(Box::new(reflective_plane()));
+        let h = 2.0_f32.sqrt() / 2.0;
+        let r = Ray::new(point(0, 0, -3), vector(0, -h, h));
+        let i = Intersection::new(2.0_f32.sqrt(), w.objects[2].as_ref());
+        let comps = prepare_computations(&i, &r, &Intersections::new(vec![i]));
+        assert_eq!(w.reflected_color(&comps, w.max_depth), Color::new(0.19032, 0.2379, 0.14274));
+    }
+
+    #[test]
+    fn test_shade_hit_reflective() {
+        let mut w = default_world();
+        w.objects.push(Box::new(reflective_plane()));
+        let h = 2.0_f32.sqrt() / 2.0;
+        let r = Ray::new(point(0, 0, -3), vector(0, -h, h));
+        let i = Intersection::new(2.0_f32.sqrt(), w.objects[2].as_ref());
+        let comps = prepare_computations(&i, &r, &Intersections::new(vec![i]));
+        assert_eq!(w.shade_hit(&comps, w.max_depth), Color::new(0.87677, 0.92436, 0.82918));
+    }
+
+    #[test]
+    fn test_color_at_mutually_reflective() {
+        let mut w = World::new();
+        w.lights.push(PointLight::new(point(0, 0, 0), Color::new(1, 1, 1)));
+        let mut lower = Plane::new();
+        lower.material.reflective = 1.0;
+        lower.set_transform(&transformations::translate(0, -1, 0));
+        let mut upper = Plane::new();
+        upper.material.reflective = 1.0;
+        upper.set_transform(&transformations::translate(0, 1, 0));
+        w.objects = vec![Box::new(lower), Box::new(upper)];
+        let r = Ray::new(point(0, 0, 0), vector(0, 1, 0));
+        // Terminating at all is what matters here
+        let c = w.color_at(&r);
+        assert!(c.red > 0.0);
+    }
+
+    #[test]
+    fn test_reflected_color_max_depth() {
+        let mut w = default_world();
+        w.objects.push(Box::new(reflective_plane()));
+        let h = 2.0_f32.sqrt() / 2.0;
+        let r = Ray::new(point(0, 0, -3), vector(0, -h, h));
+        let i = Intersection::new(2.0_f32.sqrt(), w.objects[2].as_ref());
+        let comps = prepare_computations(&i, &r, &Intersections::new(vec![i]));
+        assert_eq!(w.reflected_color(&comps, 0), Color::new(0, 0, 0));
+    }
+
+    #[test]
+    fn test_max_depth_configurable() {
+        let mut w = default_world();
+        w.objects.push(Box::new(reflective_plane()));
+        let h = 2.0_f32.sqrt() / 2.0;
+        let r = Ray::new(point(0, 0, -3), vector(0, -h, h));
+        let reflected = w.color_at(&r);
+        w.max_depth = 0;
+        let direct = w.color_at(&r);
+        assert_eq!(reflected - direct, Color::new(0.19032, 0.2379, 0.14274));
+    }
+
+    fn glass_sphere() -> Sphere {
+        let mut s = Sphere::new();
+        s.material.transparency = 1.0;
+        s.material.refractive_index = 1.5;
+        s
+    }
+
+    #[test]
+    fn test_refracted_color_opaque() {
+        let w = default_world();
+        let r = Ray::new(point(0, 0, -5), vector(0, 0, 1));
+        let xs = Intersections::new(vec![Intersection::new(4, w.objects[0].as_ref()), Intersection::new(6, w.objects[0].as_ref())]);
+        let comps = prepare_computations(&xs.v[0], &r, &xs);
+        assert_eq!(w.refracted_color(&comps, 5), Color::new(0, 0, 0));
+    }
+
+    #[test]
+    fn test_refracted_color_max_depth() {
+        let mut w = default_world();
+        w.objects[0] = Box::new(glass_sphere());
+        let r = Ray::new(point(0, 0, -5), vector(0, 0, 1));
+        let xs = Intersections::new(vec![Intersection::new(4, w.objects[0].as_ref()), Intersection::new(6, w.objects[0].as_ref())]);
+        let comps = prepare_computations(&xs.v[0], &r, &xs);
+        assert_eq!(w.refracted_color(&comps, 0), Color::new(0, 0, 0));
+    }
+
+    #[test]
+    fn test_refracted_color_total_internal_reflection() {
+        let mut w = default_world();
+        w.objects[0] = Box::new(glass_sphere());
+        let h = 2.0_f32.sqrt() / 2.0;
+        let r = Ray::new(point(0, 0, h), vector(0, 1, 0));
+        let xs = Intersections::new(vec![Intersection::new(-h, w.objects[0].as_ref()), Intersection::new(h, w.objects[0].as_ref())]);
+        let comps = prepare_computations(&xs.v[1], &r, &xs);
+        assert_eq!(w.refracted_color(&comps, 5), Color::new(0, 0, 0));
+    }
+
+    #[test]
+    fn test_refracted_color() {
+        let mut a = Sphere::new();
+        a.material.color = Color::new(0.8, 1.0, 0.6);
+        a.material.diffuse = 0.7;
+        a.material.specular = 0.2;
+        a.material.ambient = 1.0;
+        a.material.pattern = Some(Arc::new(TestPattern::new()));
+        let mut b = glass_sphere();
+        b.set_transform(&transformations::scaling(0.5, 0.5, 0.5));
+        let mut w = default_world();
+        w.objects = vec![Box::new(a), Box::new(b)];
+        let r = Ray::new(point(0, 0, 0.1), vector(0, 1, 0));
+        let xs = Intersections::new(vec![
+            Intersection::new(-0.9899, w.objects[0].as_ref()), Intersection::new(-0.4899, w.objects[1].as_ref()),
+            Intersection::new(0.4899, w.objects[1].as_ref()), Intersection::new(0.9899, w.objects[0].as_ref())
+        ]);
+        let comps = prepare_computations(&xs.v[2], &r, &xs);
+        assert_eq!(w.refracted_color(&comps, 5), Color::new(0, 0.99888, 0.04725));
+    }
+
+    #[test]
+    fn test_shade_hit_transparent() {
+        let mut w = default_world();
+        let mut floor = Plane::new();
+        floor.set_transform(&transformations::translate(0, -1, 0));
+        floor.material.transparency = 0.5;
+        floor.material.refractive_index = 1.5;
+        let mut ball = Sphere::new();
+        ball.material.color = Color::new(1, 0, 0);
+        ball.material.ambient = 0.5;
+        ball.set_transform(&transformations::translate(0, -3.5, -0.5));
+        w.objects.push(Box::new(floor));
+        w.objects.push(Box::new(ball));
+        let h = 2.0_f32.sqrt() / 2.0;
+        let r = Ray::new(point(0, 0, -3), vector(0, -h, h));
+        let xs = Intersections::new(vec![Intersection::new(2.0_f32.sqrt(), w.objects[2].as_ref())]);
+        let comps = prepare_computations(&xs.v[0], &r, &xs);
+        assert_eq!(w.shade_hit(&comps, 5), Color::new(0.93642, 0.68642, 0.68642));
+    }
+
+    #[test]
+    fn test_shade_hit_reflective_transparent() {
+        let mut w = default_world();
+        let mut floor = Plane::new();
+        floor.set_transform(&transformations::translate(0, -1, 0));
+        floor.material.reflective = 0.5;
+        floor.material.transparency = 0.5;
+        floor.material.refractive_index = 1.5;
+        let mut ball = Sphere::new();
+        ball.material.color = Color::new(1, 0, 0);
+        ball.material.ambient = 0.5;
+        ball.set_transform(&transformations::translate(0, -3.5, -0.5));
+        w.objects.push(Box::new(floor));
+        w.objects.push(Box::new(ball));
+        let h = 2.0_f32.sqrt() / 2.0;
+        let r = Ray::new(point(0, 0, -3), vector(0, -h, h));
+        let xs = Intersections::new(vec![Intersection::new(2.0_f32.sqrt(), w.objects[2].as_ref())]);
+        let comps = prepare_computations(&xs.v[0], &r, &xs);
+        assert_eq!(w.shade_hit(&comps, 5), Color::new(0.93391, 0.69643, 0.69243));
+    }
+}